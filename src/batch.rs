@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use rayon::prelude::*;
+use serde::Serialize;
+
+use crate::compression;
+use crate::error::Result;
+use crate::ipl3::Ipl3Match;
+use crate::{analyse_rom, load_rom, AnalysisOptions, RomReport};
+
+const ROM_EXTENSIONS: [&str; 3] = ["z64", "n64", "v64"];
+
+#[derive(Debug, Clone, Copy)]
+pub enum BatchFormat {
+    Csv,
+    Json,
+}
+
+impl FromStr for BatchFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(BatchFormat::Csv),
+            "json" => Ok(BatchFormat::Json),
+            _ => Err(format!("unknown batch format `{}`, expected csv or json", s)),
+        }
+    }
+}
+
+/// Summary of one rom. If it could not be analysed, `error` says why and the rest is left empty.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BatchRow {
+    pub file: String,
+    pub title: Option<String>,
+    pub game_code: Option<String>,
+    /// Name of the CIC, suffixed with `?` if it is only the nearest reference dump
+    pub cic: Option<String>,
+    pub region_count: usize,
+    /// Total size of the code regions
+    pub code_bytes: usize,
+    /// Number of compressed segments of each type
+    pub compressed: BTreeMap<String, usize>,
+    /// Compilers the heuristics narrowed the code down to, sorted; empty if nothing could be ruled out
+    pub compilers: Vec<String>,
+    pub error: Option<String>,
+}
+
+impl BatchRow {
+    fn from_report(file: String, report: &RomReport) -> BatchRow {
+        let cic = report.cic.as_ref().and_then(|cic| match cic.method {
+            Ipl3Match::Nearest(_) => cic.cic.map(|info| format!("{}?", info.name())),
+            _ => cic.cic.map(|info| info.name()),
+        });
+
+        let compressed = compression::Type::ALL
+            .iter()
+            .map(|&algorithm| {
+                let count = report.compressed_segments.as_ref().map_or(0, |segments| {
+                    segments
+                        .iter()
+                        .filter(|seg| seg.algorithm() == algorithm)
+                        .count()
+                });
+                (algorithm.to_string(), count)
+            })
+            .collect();
+
+        let mut compilers = report
+            .compiler
+            .as_ref()
+            .map(|compiler| {
+                compiler
+                    .possible_compilers
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        compilers.sort();
+
+        BatchRow {
+            file,
+            title: report.header.as_ref().map(|header| header.name.clone()),
+            game_code: report.header.as_ref().map(|header| header.full_game_code()),
+            cic,
+            region_count: report.code_regions.as_ref().map_or(0, Vec::len),
+            code_bytes: report
+                .code_regions
+                .iter()
+                .flatten()
+                .map(|region| region.rom_end() - region.rom_start())
+                .sum(),
+            compressed,
+            compilers,
+            error: None,
+        }
+    }
+
+    fn from_error(file: String, error: String) -> BatchRow {
+        BatchRow {
+            file,
+            error: Some(error),
+            ..Default::default()
+        }
+    }
+}
+
+/// Every file in `dir` with a rom extension, sorted by name
+pub fn find_roms(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut roms = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_rom = path
+            .extension()
+            .and_then(OsStr::to_str)
+            .is_some_and(|ext| ROM_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()));
+        if path.is_file() && is_rom {
+            roms.push(path);
+        }
+    }
+
+    roms.sort();
+    Ok(roms)
+}
+
+/// Analyse one rom, recording any failure in the row rather than returning it
+pub fn analyse_file(path: &Path, options: &AnalysisOptions) -> BatchRow {
+    let file = path
+        .file_name()
+        .map_or_else(|| path.display().to_string(), |name| name.to_string_lossy().into_owned());
+
+    let rom_bytes = match fs::read(path)
+        .map_err(Into::into)
+        .and_then(|bytes| load_rom(bytes, None, options.raw))
+    {
+        Ok((rom_bytes, _)) => rom_bytes,
+        Err(err) => return BatchRow::from_error(file, err.to_string()),
+    };
+
+    // One malformed rom shouldn't take the rest of the run down with it
+    match panic::catch_unwind(AssertUnwindSafe(|| analyse_rom(&rom_bytes, options))) {
+        Ok(report) => BatchRow::from_report(file, &report),
+        Err(_) => BatchRow::from_error(file, "analysis panicked".to_string()),
+    }
+}
+
+/// Analyse every rom in `dir` in parallel, one rom per worker
+pub fn analyse_dir(dir: &Path, options: &AnalysisOptions) -> Result<Vec<BatchRow>> {
+    let roms = find_roms(dir)?;
+
+    Ok(roms
+        .par_iter()
+        .with_max_len(1)
+        .map(|path| analyse_file(path, options))
+        .collect())
+}
+
+pub fn write_csv(rows: &[BatchRow], writer: impl io::Write) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+
+    let mut header = vec!["file", "title", "game_code", "cic", "region_count", "code_bytes"];
+    let type_names = compression::Type::ALL
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    header.extend(type_names.iter().map(String::as_str));
+    header.extend(["compilers", "error"]);
+    writer.write_record(&header).map_err(io::Error::from)?;
+
+    for row in rows {
+        let mut record = vec![
+            row.file.clone(),
+            row.title.clone().unwrap_or_default(),
+            row.game_code.clone().unwrap_or_default(),
+            row.cic.clone().unwrap_or_default(),
+            row.region_count.to_string(),
+            row.code_bytes.to_string(),
+        ];
+        record.extend(
+            type_names
+                .iter()
+                .map(|name| row.compressed.get(name).copied().unwrap_or(0).to_string()),
+        );
+        record.push(row.compilers.join(";"));
+        record.push(row.error.clone().unwrap_or_default());
+        writer.write_record(&record).map_err(io::Error::from)?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn write_json(rows: &[BatchRow], writer: impl io::Write) -> Result<()> {
+    serde_json::to_writer_pretty(writer, rows).map_err(io::Error::from)?;
+    Ok(())
+}
@@ -0,0 +1,173 @@
+use crate::header::HEADER_SIZE;
+use crate::utils::*;
+use crate::{BOOT_SEGMENT_SIZE, IPL3_END, WORD_SIZE};
+
+const CRC1_OFFSET: usize = 0x10;
+const CRC2_OFFSET: usize = 0x14;
+
+/// The seed and final mixing step differ between CICs; PAL CICs use the same algorithm as their NTSC counterparts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootChecksum {
+    /// 6101, 6102, 7101, 7102
+    Cic6102,
+    /// 6103, 7103
+    Cic6103,
+    /// 6105, 7105
+    Cic6105,
+    /// 6106, 7106
+    Cic6106,
+}
+
+impl BootChecksum {
+    const fn seed(&self) -> u32 {
+        match self {
+            BootChecksum::Cic6102 => 0xF8CA4DDC,
+            BootChecksum::Cic6103 => 0xA3886759,
+            BootChecksum::Cic6105 => 0xDF26F436,
+            BootChecksum::Cic6106 => 0x1FEA617A,
+        }
+    }
+
+    /// Compute CRC1 and CRC2 for a big-endian rom, or `None` if it is too small to contain the checksummed area
+    pub fn calculate(&self, rom_bytes: &[u8]) -> Option<(u32, u32)> {
+        let checksummed = rom_bytes.get(IPL3_END..IPL3_END + BOOT_SEGMENT_SIZE)?;
+        let seed = self.seed();
+        let (mut t1, mut t2, mut t3, mut t4, mut t5, mut t6) = (seed, seed, seed, seed, seed, seed);
+
+        for (i, chunk) in checksummed.chunks_exact(WORD_SIZE).enumerate() {
+            let rom_addr = IPL3_END + i * WORD_SIZE;
+            let d = read_be_word(chunk);
+
+            let (sum, overflowed) = t6.overflowing_add(d);
+            if overflowed {
+                t4 = t4.wrapping_add(1);
+            }
+            t6 = sum;
+            t3 ^= d;
+
+            let r = d.rotate_left(d & 0x1F);
+            t5 = t5.wrapping_add(r);
+
+            if t2 > d {
+                t2 ^= r;
+            } else {
+                t2 ^= t6 ^ d;
+            }
+
+            if *self == BootChecksum::Cic6105 {
+                // 6105 mixes in words from a table in its own IPL3
+                let table_word = read_be_word(&rom_bytes[HEADER_SIZE + 0x710 + (rom_addr & 0xFF)..]);
+                t1 = t1.wrapping_add(table_word ^ d);
+            } else {
+                t1 = t1.wrapping_add(t5 ^ d);
+            }
+        }
+
+        Some(match self {
+            BootChecksum::Cic6103 => ((t6 ^ t4).wrapping_add(t3), (t5 ^ t2).wrapping_add(t1)),
+            BootChecksum::Cic6106 => (
+                t6.wrapping_mul(t4).wrapping_add(t3),
+                t5.wrapping_mul(t2).wrapping_add(t1),
+            ),
+            _ => (t6 ^ t4 ^ t3, t5 ^ t2 ^ t1),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ChecksumReport {
+    /// CRC1 and CRC2 as stored in the header
+    pub header: (u32, u32),
+    pub calculated: (u32, u32),
+}
+
+impl ChecksumReport {
+    pub fn matches(&self) -> bool {
+        self.header == self.calculated
+    }
+}
+
+/// Check the header CRCs of a big-endian rom, or `None` if it is too small
+pub fn verify(rom_bytes: &[u8], algorithm: BootChecksum) -> Option<ChecksumReport> {
+    let calculated = algorithm.calculate(rom_bytes)?;
+    Some(ChecksumReport {
+        header: (
+            read_be_word(&rom_bytes[CRC1_OFFSET..]),
+            read_be_word(&rom_bytes[CRC2_OFFSET..]),
+        ),
+        calculated,
+    })
+}
+
+/// Overwrite the header CRCs of a big-endian rom with freshly calculated ones. Returns `None` (leaving the rom
+/// untouched) if it is too small.
+pub fn fix(rom_bytes: &mut [u8], algorithm: BootChecksum) -> Option<(u32, u32)> {
+    let (crc1, crc2) = algorithm.calculate(rom_bytes)?;
+    rom_bytes[CRC1_OFFSET..CRC1_OFFSET + WORD_SIZE].copy_from_slice(&crc1.to_be_bytes());
+    rom_bytes[CRC2_OFFSET..CRC2_OFFSET + WORD_SIZE].copy_from_slice(&crc2.to_be_bytes());
+    Some((crc1, crc2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALGORITHMS: [BootChecksum; 4] = [
+        BootChecksum::Cic6102,
+        BootChecksum::Cic6103,
+        BootChecksum::Cic6105,
+        BootChecksum::Cic6106,
+    ];
+
+    /// A rom just big enough to checksum, filled with xorshift32 words so every branch of the mixing is taken
+    fn synthetic_rom() -> Vec<u8> {
+        let mut state: u32 = 0x12345678;
+        (0..(IPL3_END + BOOT_SEGMENT_SIZE) / WORD_SIZE)
+            .flat_map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state.to_be_bytes()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn calculate_matches_n64crc() {
+        // Calculated independently with n64crc
+        let expected = [
+            (0x50F87305, 0xC42613B2),
+            (0x45CB6248, 0x9CA3D7E4),
+            (0x791D1537, 0x35E5B421),
+            (0x63AED81B, 0xE276EA71),
+        ];
+
+        let rom = synthetic_rom();
+        for (algorithm, crcs) in ALGORITHMS.into_iter().zip(expected) {
+            assert_eq!(algorithm.calculate(&rom), Some(crcs), "{:?}", algorithm);
+        }
+    }
+
+    #[test]
+    fn fix_then_verify_matches() {
+        for algorithm in ALGORITHMS {
+            let mut rom = synthetic_rom();
+            assert!(!verify(&rom, algorithm).unwrap().matches(), "{:?}", algorithm);
+
+            let crcs = fix(&mut rom, algorithm).unwrap();
+            let report = verify(&rom, algorithm).unwrap();
+            assert!(report.matches(), "{:?}", algorithm);
+            assert_eq!(report.header, crcs);
+        }
+    }
+
+    #[test]
+    fn too_small_is_none() {
+        let mut rom = synthetic_rom();
+        rom.truncate(IPL3_END + BOOT_SEGMENT_SIZE - WORD_SIZE);
+        for algorithm in ALGORITHMS {
+            assert_eq!(algorithm.calculate(&rom), None);
+            assert!(fix(&mut rom, algorithm).is_none());
+        }
+    }
+}
@@ -1,6 +1,5 @@
 use std::collections::HashSet;
 use std::fmt::Display;
-use std::io;
 use strum::EnumCount;
 use strum::IntoEnumIterator;
 use strum_macros;
@@ -8,7 +7,6 @@ use strum_macros::EnumIter;
 
 use crate::findcode;
 use crate::utils::*;
-use crate::Args;
 use crate::INSTRUCTION_SIZE;
 use findcode::analysis::*;
 use findcode::RomRegion;
@@ -216,98 +214,98 @@ pub fn break_6_7_pattern(
     )
 }
 
-pub fn analyse(_args: &Args, rom_bytes: &[u8], regions: &[RomRegion]) -> io::Result<()> {
-    // Start with all possible and narrow it down
-    // let mut overall_possible_compilers = Compiler::iter().collect::<HashSet<_>>();
-    let mut overall_possible_compilers = HashSet::<Compiler>::new();
-
-    let mut total_b_count = 0;
-    let mut total_j_count = 0;
-
-    let mut total_float_load_pattern_count = 0;
-    let mut total_isolated_mtc1_count = 0;
-
-    let mut total_break_6_pattern_count = 0;
-    let mut total_other_break_6_count = 0;
-    let mut total_break_7_pattern_count = 0;
-    let mut total_other_break_7_count = 0;
-
-    for region in regions {
-        let mut regional_possible_compilers = Compiler::iter().collect::<HashSet<_>>();
-
-        print!(
-            "[{:7X}, {:7X}):  ",
-            region.rom_start(),
-            region.rom_end(),
-        );
-        let (b_count, j_count) = b_vs_j(rom_bytes, region, &mut regional_possible_compilers);
-        total_b_count += b_count;
-        total_j_count += j_count;
-        print!(
-            "b: {:4}, j: {:4}  ",
+/// Raw counts gathered by the heuristics, for a single region or summed over several
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CompilerEvidence {
+    pub b_count: i32,
+    pub j_count: i32,
+    pub float_load_pattern_count: i32,
+    pub isolated_mtc1_count: i32,
+    pub break_6_pattern_count: i32,
+    pub other_break_6_count: i32,
+    pub break_7_pattern_count: i32,
+    pub other_break_7_count: i32,
+}
+
+impl CompilerEvidence {
+    fn add(&mut self, other: &CompilerEvidence) {
+        self.b_count += other.b_count;
+        self.j_count += other.j_count;
+        self.float_load_pattern_count += other.float_load_pattern_count;
+        self.isolated_mtc1_count += other.isolated_mtc1_count;
+        self.break_6_pattern_count += other.break_6_pattern_count;
+        self.other_break_6_count += other.other_break_6_count;
+        self.break_7_pattern_count += other.break_7_pattern_count;
+        self.other_break_7_count += other.other_break_7_count;
+    }
+}
+
+#[derive(Debug)]
+pub struct RegionCompilerReport {
+    pub rom_start: usize,
+    pub rom_end: usize,
+    pub evidence: CompilerEvidence,
+    pub possible_compilers: HashSet<Compiler>,
+}
+
+#[derive(Debug)]
+pub struct CompilerReport {
+    pub regions: Vec<RegionCompilerReport>,
+    pub total: CompilerEvidence,
+    /// Union of the compilers of every region that could be narrowed down
+    pub possible_compilers: HashSet<Compiler>,
+}
+
+/// Run every heuristic over a single region
+pub fn analyse_region(rom_bytes: &[u8], region: &RomRegion) -> RegionCompilerReport {
+    let mut possible_compilers = Compiler::iter().collect::<HashSet<_>>();
+
+    let (b_count, j_count) = b_vs_j(rom_bytes, region, &mut possible_compilers);
+
+    let (float_load_pattern_count, isolated_mtc1_count) =
+        float_load_pattern(rom_bytes, region, &mut possible_compilers);
+
+    let (break_6_pattern_count, other_break_6_count, break_7_pattern_count, other_break_7_count) =
+        break_6_7_pattern(rom_bytes, region, &mut possible_compilers);
+
+    RegionCompilerReport {
+        rom_start: region.rom_start(),
+        rom_end: region.rom_end(),
+        evidence: CompilerEvidence {
             b_count,
-            j_count
-        );
-
-        let (float_load_pattern_count, isolated_mtc1_count) =
-            float_load_pattern(rom_bytes, region, &mut regional_possible_compilers);
-        total_float_load_pattern_count += float_load_pattern_count;
-        total_isolated_mtc1_count += isolated_mtc1_count;
-        print!(
-            "lui-(ori)-mtc1: {:4}, isolated mtc1: {:4}  ",
-            float_load_pattern_count, isolated_mtc1_count
-        );
-
-        let (
+            j_count,
+            float_load_pattern_count,
+            isolated_mtc1_count,
             break_6_pattern_count,
             other_break_6_count,
             break_7_pattern_count,
             other_break_7_count,
-        ) = break_6_7_pattern(rom_bytes, region, &mut regional_possible_compilers);
-        total_break_6_pattern_count += break_6_pattern_count;
-        total_other_break_6_count += other_break_6_count;
-        total_break_7_pattern_count += break_7_pattern_count;
-        total_other_break_7_count += other_break_7_count;
-        print!(
-            "break 6: pattern: {:2}, other: {:2}, break 7: pattern: {:2}, other: {:2}  ",
-            break_6_pattern_count, other_break_6_count, break_7_pattern_count, other_break_7_count,
-        );
-
-        if regional_possible_compilers.len() < Compiler::COUNT {
-            overall_possible_compilers.extend(&regional_possible_compilers);
-        }
-        print!("Possible compilers: ");
-        let mut compiler_list = regional_possible_compilers
-            .into_iter()
-            .map(|v| format!("{:?}", v))
-            .collect::<Vec<_>>();
-        compiler_list.sort();
-        println!("{{{}}}", compiler_list.join(","));
+        },
+        possible_compilers,
     }
+}
 
-    println!();
-    println!("Total b/j: b: {:4}, j: {:4}", total_b_count, total_j_count);
-    println!(
-        "Total mtc1: lui-(ori)-mtc1: {:4}, isolated mtc1: {:4}",
-        total_float_load_pattern_count, total_isolated_mtc1_count
-    );
-    println!(
-        "Total break 6: pattern {:2}, other {:2}, break 7: pattern {:2}, other {:2} ",
-        total_break_6_pattern_count,
-        total_other_break_6_count,
-        total_break_7_pattern_count,
-        total_other_break_7_count,
-    );
-
-    println!();
-    println!("Possible: ");
-    let mut compiler_list = overall_possible_compilers
-        .into_iter()
-        .map(|v| format!("{}", v))
-        .collect::<Vec<_>>();
-    compiler_list.sort();
-    print!("{}", compiler_list.join(", "));
-    println!();
-
-    Ok(())
+pub fn analyse(rom_bytes: &[u8], regions: &[RomRegion]) -> CompilerReport {
+    // Start with all possible and narrow it down
+    // let mut overall_possible_compilers = Compiler::iter().collect::<HashSet<_>>();
+    let mut possible_compilers = HashSet::<Compiler>::new();
+    let mut total = CompilerEvidence::default();
+
+    let regions = regions
+        .iter()
+        .map(|region| {
+            let report = analyse_region(rom_bytes, region);
+            total.add(&report.evidence);
+            if report.possible_compilers.len() < Compiler::COUNT {
+                possible_compilers.extend(&report.possible_compilers);
+            }
+            report
+        })
+        .collect();
+
+    CompilerReport {
+        regions,
+        total,
+        possible_compilers,
+    }
 }
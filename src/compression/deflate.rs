@@ -0,0 +1,180 @@
+//! Deflate in its various wrappers: zlib, gzip (e.g. Donkey Kong 64), and the `11 72` header Rare used in Banjo-Kazooie
+//! and others, which is a decompressed size followed by a raw deflate stream.
+
+use flate2::{Decompress, FlushDecompress, Status};
+
+use super::{check_size, CompressionFormat, Decoded, DecodeError, MAX_DECOMPRESSED_SIZE};
+use crate::utils::*;
+
+const CRC32_ALG: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+
+/// The headers zlib writes with its default 32KiB window, at each compression level from fastest to best. Smaller
+/// windows are allowed, but too rare to be worth checking every word for.
+const ZLIB_HEADERS: &[&[u8]] = &[&[0x78, 0x01], &[0x78, 0x5E], &[0x78, 0x9C], &[0x78, 0xDA]];
+
+const GZIP_MAGIC: &[u8] = &[0x1F, 0x8B, 0x08];
+const GZIP_HEADER_SIZE: usize = 10;
+const GZIP_TRAILER_SIZE: usize = 8;
+const GZIP_FHCRC: u8 = 1 << 1;
+const GZIP_FEXTRA: u8 = 1 << 2;
+const GZIP_FNAME: u8 = 1 << 3;
+const GZIP_FCOMMENT: u8 = 1 << 4;
+
+const RARE_MAGIC: &[u8] = &[0x11, 0x72];
+const RARE_HEADER_SIZE: usize = 6;
+
+/// Size of the first output buffer if the decompressed size isn't known; it is doubled as needed
+const INITIAL_OUTPUT_SIZE: usize = 0x1000;
+
+/// Inflate a stream at the start of `bytes`, returning the data and the number of bytes consumed. A zlib stream's
+/// Adler-32 is checked by the inflater.
+fn inflate(bytes: &[u8], zlib_header: bool, size: Option<usize>) -> Result<(Vec<u8>, usize), DecodeError> {
+    let mut inflater = Decompress::new(zlib_header);
+    let mut out = Vec::with_capacity(size.unwrap_or(INITIAL_OUTPUT_SIZE));
+
+    loop {
+        if out.len() == out.capacity() {
+            if out.len() >= MAX_DECOMPRESSED_SIZE {
+                return Err(DecodeError::BadHeader);
+            }
+            out.reserve(out.len().max(INITIAL_OUTPUT_SIZE));
+        }
+
+        let consumed = inflater.total_in() as usize;
+        let produced = out.len();
+        match inflater.decompress_vec(&bytes[consumed..], &mut out, FlushDecompress::None) {
+            Ok(Status::StreamEnd) => return Ok((out, inflater.total_in() as usize)),
+            Ok(_) => {
+                // No progress with room to spare means the input ran out
+                if inflater.total_in() as usize == consumed
+                    && out.len() == produced
+                    && out.len() < out.capacity()
+                {
+                    return Err(DecodeError::Truncated);
+                }
+            }
+            Err(_) => return Err(DecodeError::BadStream),
+        }
+    }
+}
+
+pub struct Zlib;
+
+impl CompressionFormat for Zlib {
+    fn name(&self) -> &'static str {
+        "zlib"
+    }
+    fn signatures(&self) -> &'static [&'static [u8]] {
+        ZLIB_HEADERS
+    }
+    fn detect(&self, bytes: &[u8]) -> bool {
+        // Deflate with a window of at most 32KiB, no preset dictionary, and the header check bits right
+        bytes.len() >= 2
+            && bytes[0] & 0x0F == 8
+            && bytes[0] >> 4 <= 7
+            && bytes[1] & 0x20 == 0
+            && u16::from_be_bytes([bytes[0], bytes[1]]).is_multiple_of(31)
+    }
+    fn decompress(&self, bytes: &[u8]) -> Result<Decoded, DecodeError> {
+        let (data, compressed_size) = inflate(bytes, true, None)?;
+        Ok(Decoded {
+            data,
+            compressed_size,
+        })
+    }
+}
+
+pub struct Gzip;
+
+impl CompressionFormat for Gzip {
+    fn name(&self) -> &'static str {
+        "gzip"
+    }
+    fn signatures(&self) -> &'static [&'static [u8]] {
+        &[GZIP_MAGIC]
+    }
+    fn detect(&self, bytes: &[u8]) -> bool {
+        // The reserved flags must be clear
+        bytes.len() >= GZIP_HEADER_SIZE && bytes.starts_with(GZIP_MAGIC) && bytes[3] & 0xE0 == 0
+    }
+    fn decompress(&self, bytes: &[u8]) -> Result<Decoded, DecodeError> {
+        if bytes.len() < GZIP_HEADER_SIZE {
+            return Err(DecodeError::Truncated);
+        }
+        let flags = bytes[3];
+        let mut pos = GZIP_HEADER_SIZE;
+        let skip_string = |pos: usize| {
+            bytes[pos..]
+                .iter()
+                .position(|&b| b == 0)
+                .map(|len| pos + len + 1)
+                .ok_or(DecodeError::Truncated)
+        };
+
+        if flags & GZIP_FEXTRA != 0 {
+            let len = bytes
+                .get(pos..pos + 2)
+                .ok_or(DecodeError::Truncated)?;
+            pos += 2 + u16::from_le_bytes([len[0], len[1]]) as usize;
+        }
+        if flags & GZIP_FNAME != 0 {
+            pos = skip_string(pos.min(bytes.len()))?;
+        }
+        if flags & GZIP_FCOMMENT != 0 {
+            pos = skip_string(pos.min(bytes.len()))?;
+        }
+        if flags & GZIP_FHCRC != 0 {
+            pos += 2;
+        }
+        if pos > bytes.len() {
+            return Err(DecodeError::Truncated);
+        }
+
+        let (data, consumed) = inflate(&bytes[pos..], false, None)?;
+        pos += consumed;
+        let trailer = bytes
+            .get(pos..pos + GZIP_TRAILER_SIZE)
+            .ok_or(DecodeError::Truncated)?;
+        let crc32 = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        let isize = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+        if crc32 != CRC32_ALG.checksum(&data) || isize != data.len() as u32 {
+            return Err(DecodeError::BadChecksum);
+        }
+
+        Ok(Decoded {
+            data,
+            compressed_size: pos + GZIP_TRAILER_SIZE,
+        })
+    }
+}
+
+/// Rare's wrapper, named after its magic. There is no checksum, so the decompressed size in the header is what
+/// validates it.
+pub struct Rare;
+
+impl CompressionFormat for Rare {
+    fn name(&self) -> &'static str {
+        "1172"
+    }
+    fn signatures(&self) -> &'static [&'static [u8]] {
+        &[RARE_MAGIC]
+    }
+    fn detect(&self, bytes: &[u8]) -> bool {
+        bytes.len() >= RARE_HEADER_SIZE && bytes.starts_with(RARE_MAGIC)
+    }
+    fn decompress(&self, bytes: &[u8]) -> Result<Decoded, DecodeError> {
+        if bytes.len() < RARE_HEADER_SIZE {
+            return Err(DecodeError::Truncated);
+        }
+        let size = check_size(read_be_word(&bytes[RARE_MAGIC.len()..]) as usize)?;
+        let (data, consumed) = inflate(&bytes[RARE_HEADER_SIZE..], false, Some(size))?;
+        if data.len() != size {
+            return Err(DecodeError::BadChecksum);
+        }
+
+        Ok(Decoded {
+            data,
+            compressed_size: RARE_HEADER_SIZE + consumed,
+        })
+    }
+}
@@ -0,0 +1,345 @@
+//! Re-encoding decoded segments with the known encoders' strategies, to find which one produced the original bytes.
+//! Matching decomps have to rebuild compressed segments byte-for-byte, which needs the same encoder.
+
+use std::fmt::Display;
+
+use rayon::prelude::*;
+
+use super::CompressedSegment;
+
+/// How an encoder chooses between literals and back-references
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// Nintendo's encoder: the longest match, the farthest one if there is a tie, but a literal instead if the next
+    /// position has a match at least 2 longer
+    Nintendo,
+    /// The longest match, the nearest one if there is a tie, as most homebrew tools do
+    Greedy,
+    /// The smallest possible output, as the "slow" encoders produce
+    Optimal,
+}
+
+impl Strategy {
+    pub const ALL: [Strategy; 3] = [Strategy::Nintendo, Strategy::Greedy, Strategy::Optimal];
+
+    /// Stable identifier for the strategy, for machine-readable output
+    pub fn name(&self) -> &'static str {
+        match self {
+            Strategy::Nintendo => "nintendo",
+            Strategy::Greedy => "greedy",
+            Strategy::Optimal => "optimal",
+        }
+    }
+}
+
+impl Display for Strategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token {
+    Literal(u8),
+    /// Copy `len` bytes from `dist` bytes back
+    Match { dist: usize, len: usize },
+}
+
+/// The limits of a format's back-references, and their cost in bits for `Strategy::Optimal`
+#[derive(Debug, Clone, Copy)]
+pub struct MatchLimits {
+    pub max_dist: usize,
+    pub min_len: usize,
+    pub max_len: usize,
+    /// Lengths up to this cost `short_cost` bits, longer ones `long_cost`
+    pub short_len: usize,
+    pub short_cost: usize,
+    pub long_cost: usize,
+    /// Cost of a literal
+    pub literal_cost: usize,
+}
+
+const HASH_SIZE: usize = 1 << 16;
+/// Most candidates looked at for one position. This is the largest window of the formats encoded, since Nintendo's
+/// encoder searches its whole window and a shorter walk would miss the farthest of a long run's matches.
+const MAX_CANDIDATES: usize = 0x1000;
+
+/// Finds the longest earlier match at each position, with chains of the earlier positions that share its first
+/// `min_len` bytes
+struct MatchFinder<'a> {
+    data: &'a [u8],
+    limits: MatchLimits,
+    /// Most recent position with each hash
+    head: Vec<Option<usize>>,
+    /// Previous position with the same hash as each position
+    prev: Vec<Option<usize>>,
+    /// Positions before this have been added to the chains
+    added: usize,
+}
+
+impl<'a> MatchFinder<'a> {
+    fn new(data: &'a [u8], limits: MatchLimits) -> Self {
+        Self {
+            data,
+            limits,
+            head: vec![None; HASH_SIZE],
+            prev: vec![None; data.len()],
+            added: 0,
+        }
+    }
+
+    fn hash(&self, pos: usize) -> Option<usize> {
+        let key = self.data.get(pos..pos + self.limits.min_len)?;
+        Some(
+            key.iter()
+                .fold(0usize, |hash, &b| hash.wrapping_mul(0x101).wrapping_add(b as usize))
+                % HASH_SIZE,
+        )
+    }
+
+    fn add_until(&mut self, pos: usize) {
+        while self.added < pos.min(self.data.len()) {
+            if let Some(hash) = self.hash(self.added) {
+                self.prev[self.added] = self.head[hash];
+                self.head[hash] = Some(self.added);
+            }
+            self.added += 1;
+        }
+    }
+
+    fn match_len(&self, candidate: usize, pos: usize) -> usize {
+        let max_len = self.limits.max_len.min(self.data.len() - pos);
+        (0..max_len)
+            .take_while(|&i| self.data[candidate + i] == self.data[pos + i])
+            .count()
+    }
+
+    /// The longest match at `pos` as `(dist, len)`, if it is at least the minimum length. The chain is walked from the
+    /// nearest candidate, so the farthest of equally long matches is only found by walking all of them; the nearest
+    /// stops at the first match of the maximum length.
+    fn longest(&mut self, pos: usize, farthest: bool) -> Option<(usize, usize)> {
+        self.add_until(pos);
+        let hash = self.hash(pos)?;
+        let window_start = pos.saturating_sub(self.limits.max_dist);
+        let max_len = self.limits.max_len.min(self.data.len() - pos);
+
+        let mut best: Option<(usize, usize)> = None;
+        let mut next = self.head[hash];
+        for _ in 0..MAX_CANDIDATES {
+            let Some(candidate) = next.filter(|&candidate| candidate >= window_start) else {
+                break;
+            };
+            next = self.prev[candidate];
+
+            // A candidate can only win if it reaches the length needed, so checking the last byte of that first rules
+            // most out cheaply
+            let best_len = best.map_or(0, |(_, len)| len);
+            let needed = if farthest { best_len } else { best_len + 1 };
+            let reaches = |len: usize| len == 0 || self.data[candidate + len - 1] == self.data[pos + len - 1];
+            if needed > max_len || !reaches(needed) {
+                continue;
+            }
+            let len = self.match_len(candidate, pos);
+            if len >= self.limits.min_len && len >= needed {
+                best = Some((pos - candidate, len));
+                if len == max_len && !farthest {
+                    break;
+                }
+            }
+        }
+        best
+    }
+}
+
+/// Split `data` into literals and back-references the way `strategy` does
+pub fn tokenise(data: &[u8], limits: MatchLimits, strategy: Strategy) -> Vec<Token> {
+    let mut finder = MatchFinder::new(data, limits);
+    let mut tokens = Vec::new();
+
+    match strategy {
+        Strategy::Nintendo | Strategy::Greedy => {
+            let farthest = strategy == Strategy::Nintendo;
+            let mut pos = 0;
+            // A match found by looking ahead, which is used after the literal
+            let mut pending: Option<(usize, usize)> = None;
+            while pos < data.len() {
+                let found = pending.take().or_else(|| finder.longest(pos, farthest));
+                match found {
+                    Some((dist, len)) => {
+                        if strategy == Strategy::Nintendo {
+                            let next = finder.longest(pos + 1, farthest);
+                            if next.is_some_and(|(_, next_len)| next_len >= len + 2) {
+                                tokens.push(Token::Literal(data[pos]));
+                                pending = next;
+                                pos += 1;
+                                continue;
+                            }
+                        }
+                        tokens.push(Token::Match { dist, len });
+                        pos += len;
+                    }
+                    None => {
+                        tokens.push(Token::Literal(data[pos]));
+                        pos += 1;
+                    }
+                }
+            }
+        }
+        Strategy::Optimal => {
+            let matches = (0..data.len())
+                .map(|pos| finder.longest(pos, false))
+                .collect::<Vec<_>>();
+            let cost = |len: usize| {
+                if len <= limits.short_len {
+                    limits.short_cost
+                } else {
+                    limits.long_cost
+                }
+            };
+
+            // Cheapest encoding of everything from each position onwards, and the length of its first token
+            let mut best = vec![(0usize, 0usize); data.len() + 1];
+            for pos in (0..data.len()).rev() {
+                best[pos] = (limits.literal_cost + best[pos + 1].0, 1);
+                if let Some((_, longest)) = matches[pos] {
+                    for len in limits.min_len..=longest {
+                        let total = cost(len) + best[pos + len].0;
+                        if total <= best[pos].0 {
+                            best[pos] = (total, len);
+                        }
+                    }
+                }
+            }
+
+            let mut pos = 0;
+            while pos < data.len() {
+                let len = best[pos].1;
+                match matches[pos] {
+                    Some((dist, _)) if len > 1 => tokens.push(Token::Match { dist, len }),
+                    _ => tokens.push(Token::Literal(data[pos])),
+                }
+                pos += len;
+            }
+        }
+    }
+
+    tokens
+}
+
+/// How close one strategy gets to reproducing a segment
+#[derive(Debug, Clone)]
+pub struct EncoderMatch {
+    pub strategy: Strategy,
+    /// Size of the re-encoded segment
+    pub size: usize,
+    /// Number of bytes at the start that are the same as the original
+    pub matching_prefix: usize,
+    pub exact: bool,
+}
+
+/// Every strategy's attempt at reproducing a segment
+#[derive(Debug, Clone)]
+pub struct SegmentEncoders {
+    pub segment: CompressedSegment,
+    /// In the order of `Strategy::ALL`
+    pub matches: Vec<EncoderMatch>,
+}
+
+impl SegmentEncoders {
+    /// The strategies that reproduce the segment exactly
+    pub fn exact(&self) -> impl Iterator<Item = Strategy> + '_ {
+        self.matches
+            .iter()
+            .filter(|encoder| encoder.exact)
+            .map(|encoder| encoder.strategy)
+    }
+}
+
+/// Re-encode a segment with every strategy. `None` if its format can't be encoded, or it doesn't decode.
+pub fn identify(rom_bytes: &[u8], segment: &CompressedSegment) -> Option<SegmentEncoders> {
+    let data = segment.decompress(rom_bytes).ok()?;
+    let original = &rom_bytes[segment.rom_start()..segment.rom_end()];
+
+    let matches = Strategy::ALL
+        .par_iter()
+        .map(|&strategy| {
+            let encoded = segment.algorithm().encode(&data, strategy)?;
+            let matching_prefix = encoded
+                .iter()
+                .zip(original)
+                .take_while(|(a, b)| a == b)
+                .count();
+            Some(EncoderMatch {
+                strategy,
+                size: encoded.len(),
+                matching_prefix,
+                exact: encoded == original,
+            })
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    Some(SegmentEncoders {
+        segment: segment.clone(),
+        matches,
+    })
+}
+
+/// Re-encode every segment whose format can be encoded
+pub fn identify_all(rom_bytes: &[u8], segments: &[CompressedSegment]) -> Vec<SegmentEncoders> {
+    segments
+        .par_iter()
+        .filter_map(|segment| identify(rom_bytes, segment))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression::{self, Type};
+
+    /// Repetitive data with matches of every length and distance the formats allow, and some that are too long or far
+    fn sample_data() -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut state = 1u32;
+        while data.len() < 0x3000 {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            let len = (state >> 16) as usize % 0x180;
+            let dist = (state >> 8) as usize % 0x1100 + 1;
+            if state & 0x80 == 0 && dist <= data.len() {
+                for _ in 0..len {
+                    data.push(data[data.len() - dist]);
+                }
+            } else {
+                data.extend((0..len % 0x20).map(|i| (state >> (i % 24)) as u8 & 0x1F));
+            }
+        }
+        data.resize(data.len() + 0x200, 0);
+        data
+    }
+
+    #[test]
+    fn encoded_segments_decode_to_the_input() {
+        let data = sample_data();
+        for algorithm in [Type::YAZ0, Type::YAY0, Type::MIO0] {
+            for strategy in Strategy::ALL {
+                let encoded = algorithm.encode(&data, strategy).unwrap();
+                let decoded = algorithm.decompress(&encoded).unwrap();
+                assert!(decoded.data == data, "{} {}", algorithm, strategy);
+                assert_eq!(decoded.compressed_size, encoded.len(), "{} {}", algorithm, strategy);
+            }
+        }
+    }
+
+    #[test]
+    fn identifies_the_strategy_that_encoded_a_segment() {
+        let data = sample_data();
+        for algorithm in [Type::YAZ0, Type::YAY0, Type::MIO0] {
+            for strategy in Strategy::ALL {
+                let rom_bytes = algorithm.encode(&data, strategy).unwrap();
+                let segment = &compression::find_format(&rom_bytes, 0, algorithm)[0];
+                let encoders = identify(&rom_bytes, segment).unwrap();
+                assert!(encoders.exact().any(|exact| exact == strategy), "{} {}", algorithm, strategy);
+            }
+        }
+    }
+}
@@ -0,0 +1,140 @@
+//! Hudson's LZSS, as used by the Mario Party games. There is no magic: the header is the decompressed size, then a
+//! compression type, which is 1 for LZSS.
+
+use super::{check_size, read_byte, CompressionFormat, Decoded, DecodeError};
+use crate::utils::*;
+use crate::WORD_SIZE;
+
+const HEADER_SIZE: usize = 8;
+const LZSS_TYPE: u32 = 1;
+
+/// Back-references are to a ring buffer of this size, which starts zeroed with the first byte written at `WINDOW_START`
+const WINDOW_SIZE: usize = 0x400;
+const WINDOW_START: usize = 0x3BE;
+
+/// Smallest decompressed size accepted, since almost any short run of bytes decodes
+const MIN_DECOMPRESSED_SIZE: usize = 0x40;
+/// Longest back-reference
+const MAX_MATCH: usize = 0x42;
+
+pub struct HudsonLzss;
+
+impl CompressionFormat for HudsonLzss {
+    fn name(&self) -> &'static str {
+        "Hudson"
+    }
+    fn has_magic(&self) -> bool {
+        false
+    }
+    fn detect(&self, bytes: &[u8]) -> bool {
+        bytes.len() >= HEADER_SIZE
+            && read_be_word(&bytes[WORD_SIZE..]) == LZSS_TYPE
+            && (MIN_DECOMPRESSED_SIZE..=super::MAX_DECOMPRESSED_SIZE)
+                .contains(&(read_be_word(bytes) as usize))
+    }
+    fn decompress(&self, bytes: &[u8]) -> Result<Decoded, DecodeError> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(DecodeError::Truncated);
+        }
+        let size = check_size(read_be_word(bytes) as usize)?;
+        let end = bytes.len();
+        let mut out = Vec::with_capacity(size);
+        let mut pos = HEADER_SIZE;
+
+        while out.len() < size {
+            // The flags are read from the least significant bit, 1 for a literal
+            let flags = read_byte(bytes, &mut pos, end)?;
+            for bit in 0..8 {
+                if out.len() >= size {
+                    break;
+                }
+                if flags & (1 << bit) != 0 {
+                    out.push(read_byte(bytes, &mut pos, end)?);
+                } else {
+                    let b1 = read_byte(bytes, &mut pos, end)? as usize;
+                    let b2 = read_byte(bytes, &mut pos, end)? as usize;
+                    let offset = (b2 & 0xC0) << 2 | b1;
+                    let len = (b2 & 0x3F) + 3;
+                    if !is_encodable(out.len(), offset) {
+                        return Err(DecodeError::Implausible);
+                    }
+                    for i in 0..len.min(size - out.len()) {
+                        // Reads the window as it is being written, like the game does
+                        out.push(window_at(&out, offset + i));
+                    }
+                }
+            }
+        }
+
+        Ok(Decoded {
+            data: out,
+            compressed_size: pos,
+        })
+    }
+    fn validate(&self, decoded: &Decoded) -> bool {
+        decoded.compressed_size - HEADER_SIZE < decoded.data.len()
+    }
+}
+
+/// Whether the encoder could have written a back-reference to `offset` after `written` bytes. It is Okumura's LZSS,
+/// which only matches against the last `WINDOW_SIZE - MAX_MATCH` bytes, plus the `MAX_MATCH` zeroes before the first
+/// byte. Almost anything decodes, but any other reference rules a candidate out, which random data soon hits.
+fn is_encodable(written: usize, offset: usize) -> bool {
+    // `offset` can be past `WINDOW_START` before much has been written, so add a whole window before subtracting
+    let dist = (WINDOW_START + WINDOW_SIZE + written % WINDOW_SIZE - offset % WINDOW_SIZE) % WINDOW_SIZE;
+    dist != 0 && dist <= WINDOW_SIZE - MAX_MATCH && dist <= written + MAX_MATCH
+}
+
+/// The byte at a position of the ring buffer, given everything written to it so far. The buffer is never kept, since
+/// every byte written to it is also in the output.
+fn window_at(out: &[u8], window_index: usize) -> u8 {
+    let window_index = window_index % WINDOW_SIZE;
+    // Position in `out` of the most recent write to that index, if there has been one
+    let written = (window_index + WINDOW_SIZE - WINDOW_START) % WINDOW_SIZE;
+    if written >= out.len() {
+        return 0;
+    }
+    let last = written + (out.len() - 1 - written) / WINDOW_SIZE * WINDOW_SIZE;
+    out[last]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `abc` as literals, then a reference to the `a` 3 bytes back for 6 more
+    const ABC: &[u8] = &[0, 0, 0, 9, 0, 0, 0, 1, 0b0000_0111, b'a', b'b', b'c', 0xBE, 0xC3];
+
+    #[test]
+    fn decodes_literals_and_references() {
+        let decoded = HudsonLzss.decompress(ABC).unwrap();
+        assert_eq!(decoded.data, b"abcabcabc");
+        assert_eq!(decoded.compressed_size, ABC.len());
+    }
+
+    #[test]
+    fn references_before_the_first_byte_read_zeroes() {
+        let bytes = [0, 0, 0, 4, 0, 0, 0, 1, 0, 0xBA, 0xC1];
+        assert_eq!(HudsonLzss.decompress(&bytes).unwrap().data, [0; 4]);
+    }
+
+    #[test]
+    fn rejects_references_the_encoder_could_not_make() {
+        // The end of the window, which the encoder never matches against at the start
+        let bytes = [0, 0, 0, 4, 0, 0, 0, 1, 0, 0xFF, 0xC0];
+        assert_eq!(HudsonLzss.decompress(&bytes).unwrap_err(), DecodeError::Implausible);
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        for len in 0..ABC.len() {
+            assert!(HudsonLzss.decompress(&ABC[..len]).is_err(), "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_header() {
+        let bytes = [0, 0, 0, 0, 0, 0, 0, 1, 0xFF, 0];
+        assert_eq!(HudsonLzss.decompress(&bytes).unwrap_err(), DecodeError::BadHeader);
+    }
+}
@@ -0,0 +1,140 @@
+//! Konami's LZKN64, as used by Castlevania 64 and the Goemon games. There is no magic: the header is the size of the
+//! compressed data including itself, and the stream has no end marker, so it ends where that says.
+
+use super::{read_byte, CompressionFormat, Decoded, DecodeError, MAX_DECOMPRESSED_SIZE};
+use crate::utils::*;
+
+const HEADER_SIZE: usize = 4;
+/// Smallest compressed size accepted, since almost any short run of bytes decodes
+const MIN_COMPRESSED_SIZE: usize = 0x20;
+
+/// Back-reference: length and the top of a 10-bit distance in the command, the rest of the distance in the next byte
+const COMMAND_BACK_REFERENCE: u8 = 0x00;
+/// Literals: count in the low 5 bits
+const COMMAND_LITERALS: u8 = 0x80;
+/// 0xA0-0xBF are undefined
+const COMMAND_UNDEFINED: u8 = 0xA0;
+/// Run of the next byte: length in the low 5 bits
+const COMMAND_RUN: u8 = 0xC0;
+/// Run of zeroes: length in the low 5 bits
+const COMMAND_ZERO_RUN: u8 = 0xE0;
+/// Long run of zeroes: length in the next byte
+const COMMAND_LONG_ZERO_RUN: u8 = 0xFF;
+
+pub struct Lzkn64;
+
+impl CompressionFormat for Lzkn64 {
+    fn name(&self) -> &'static str {
+        "LZKN64"
+    }
+    fn has_magic(&self) -> bool {
+        false
+    }
+    fn detect(&self, bytes: &[u8]) -> bool {
+        // The size is 24-bit, and the first command can't be a back-reference since there's nothing to refer to
+        bytes.len() > HEADER_SIZE
+            && bytes[0] == 0
+            && (MIN_COMPRESSED_SIZE..=bytes.len()).contains(&(read_be_word(bytes) as usize))
+            && bytes[HEADER_SIZE] >= COMMAND_LITERALS
+            && !(COMMAND_UNDEFINED..COMMAND_RUN).contains(&bytes[HEADER_SIZE])
+    }
+    fn decompress(&self, bytes: &[u8]) -> Result<Decoded, DecodeError> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(DecodeError::Truncated);
+        }
+        let end = read_be_word(bytes) as usize;
+        if end < HEADER_SIZE || end > bytes.len() {
+            return Err(DecodeError::BadHeader);
+        }
+        let mut out = Vec::new();
+        let mut pos = HEADER_SIZE;
+
+        while pos < end {
+            let command = read_byte(bytes, &mut pos, end)?;
+            match command {
+                COMMAND_BACK_REFERENCE..COMMAND_LITERALS => {
+                    let low = read_byte(bytes, &mut pos, end)? as usize;
+                    let dist = ((command as usize) << 8 | low) & 0x3FF;
+                    let len = (command >> 2) as usize + 2;
+                    if dist == 0 || dist > out.len() {
+                        return Err(DecodeError::BadReference);
+                    }
+                    let start = out.len() - dist;
+                    for i in 0..len {
+                        out.push(out[start + i]);
+                    }
+                }
+                COMMAND_LITERALS..COMMAND_UNDEFINED => {
+                    let len = (command & 0x1F) as usize;
+                    let literals = bytes.get(pos..pos + len).filter(|_| pos + len <= end);
+                    out.extend_from_slice(literals.ok_or(DecodeError::Truncated)?);
+                    pos += len;
+                }
+                COMMAND_UNDEFINED..COMMAND_RUN => return Err(DecodeError::BadStream),
+                COMMAND_RUN..COMMAND_ZERO_RUN => {
+                    let b = read_byte(bytes, &mut pos, end)?;
+                    out.resize(out.len() + (command & 0x1F) as usize + 2, b);
+                }
+                COMMAND_ZERO_RUN..COMMAND_LONG_ZERO_RUN => {
+                    out.resize(out.len() + (command & 0x1F) as usize + 2, 0);
+                }
+                COMMAND_LONG_ZERO_RUN => {
+                    let len = read_byte(bytes, &mut pos, end)? as usize + 2;
+                    out.resize(out.len() + len, 0);
+                }
+            }
+            if out.len() > MAX_DECOMPRESSED_SIZE {
+                return Err(DecodeError::BadHeader);
+            }
+        }
+
+        Ok(Decoded {
+            data: out,
+            compressed_size: end,
+        })
+    }
+    fn validate(&self, decoded: &Decoded) -> bool {
+        decoded.data.len() > decoded.compressed_size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `abc`, a reference 3 bytes back for 6 more, then a run of 3 `x`, a run of 2 zeroes and a long run of 18
+    const ABC: &[u8] = &[0, 0, 0, 15, 0x83, b'a', b'b', b'c', 0x10, 0x03, 0xC1, b'x', 0xE0, 0xFF, 0x10];
+
+    #[test]
+    fn decodes_every_command() {
+        let decoded = Lzkn64.decompress(ABC).unwrap();
+        let mut expected = b"abcabcabcxxx".to_vec();
+        expected.resize(expected.len() + 20, 0);
+        assert_eq!(decoded.data, expected);
+        assert_eq!(decoded.compressed_size, ABC.len());
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        for len in 0..ABC.len() {
+            assert!(Lzkn64.decompress(&ABC[..len]).is_err(), "{} bytes", len);
+        }
+        // The size in the header cuts the literals short
+        let bytes = [0, 0, 0, 6, 0x83, b'a', b'b', b'c'];
+        assert_eq!(Lzkn64.decompress(&bytes).unwrap_err(), DecodeError::Truncated);
+    }
+
+    #[test]
+    fn rejects_bad_header() {
+        let bytes = [0, 0, 0, 2, 0x83, b'a', b'b', b'c'];
+        assert_eq!(Lzkn64.decompress(&bytes).unwrap_err(), DecodeError::BadHeader);
+    }
+
+    #[test]
+    fn rejects_bad_commands() {
+        let undefined = [0, 0, 0, 5, 0xA0];
+        assert_eq!(Lzkn64.decompress(&undefined).unwrap_err(), DecodeError::BadStream);
+        let before_start = [0, 0, 0, 6, 0x10, 0x03];
+        assert_eq!(Lzkn64.decompress(&before_start).unwrap_err(), DecodeError::BadReference);
+    }
+}
@@ -0,0 +1,388 @@
+//! Compressed segments and the registry of formats they are searched for in. Each format implements
+//! `CompressionFormat`; adding one means implementing that and listing it in `Type::ALL`, nothing else. A format that
+//! also implements `encode` can have the encoder that produced a segment identified, see `encode`.
+
+pub mod deflate;
+pub mod encode;
+pub mod hudson;
+pub mod konami;
+pub mod nintendo;
+
+use std::collections::HashSet;
+use std::fmt::{Debug, Display};
+use std::ops::Deref;
+
+use rayon::prelude::*;
+
+use crate::scan::{self, PatternId, ScannerBuilder};
+use crate::utils::*;
+use crate::WORD_SIZE;
+use encode::Strategy;
+
+/// Largest decompressed size accepted: the N64's RAM with the Expansion Pak
+pub const MAX_DECOMPRESSED_SIZE: usize = 0x800000;
+
+/// Why a segment could not be decoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The decompressed size or table offsets in the header are impossible
+    BadHeader,
+    /// The data ran out before the decompressed size was reached
+    Truncated,
+    /// A back-reference points before the start of the output
+    BadReference,
+    /// The stream contains something the format doesn't allow, e.g. an undefined command or block type
+    BadStream,
+    /// The data decoded, but doesn't match the checksum or size stored with it
+    BadChecksum,
+    /// The data decoded, but is unlikely to really be in the format
+    Implausible,
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                DecodeError::BadHeader => "bad header",
+                DecodeError::Truncated => "truncated data",
+                DecodeError::BadReference => "back-reference before the start of the output",
+                DecodeError::BadStream => "invalid stream",
+                DecodeError::BadChecksum => "checksum or size mismatch",
+                DecodeError::Implausible => "implausible result",
+            }
+        )
+    }
+}
+
+/// A successfully decoded segment
+#[derive(Debug, Clone)]
+pub struct Decoded {
+    pub data: Vec<u8>,
+    /// Number of bytes of input consumed, including the header
+    pub compressed_size: usize,
+}
+
+/// A compression format that segments can be searched for in and decoded from
+pub trait CompressionFormat: Sync {
+    /// Name shown in the output and used to name extracted files, e.g. `Yaz0`
+    fn name(&self) -> &'static str;
+
+    /// Whether the format has a magic number. Formats without one pass `detect` far more often, so are only searched
+    /// for when asked.
+    fn has_magic(&self) -> bool {
+        true
+    }
+
+    /// Byte strings that a segment must start with one of. These are found for every format in a single scan of the rom,
+    /// and `detect` is only called where they are; a format with none (one recognised by its structure) has `detect`
+    /// called at every word instead.
+    fn signatures(&self) -> &'static [&'static [u8]] {
+        &[]
+    }
+
+    /// Cheap check of the header at the start of `bytes`, before trying to decode it
+    fn detect(&self, bytes: &[u8]) -> bool;
+
+    /// Decode a segment that starts at the start of `bytes`. `bytes` can run past the end of the segment.
+    fn decompress(&self, bytes: &[u8]) -> Result<Decoded, DecodeError>;
+
+    /// Whether a segment that decoded is likely to really be in this format, rather than data that happens to decode
+    fn validate(&self, decoded: &Decoded) -> bool {
+        !decoded.data.is_empty()
+    }
+
+    /// The splat segment type that handles the format, if there is one
+    fn splat_type(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// Encode `data` the way `strategy` does, if the format can be encoded
+    fn encode(&self, _data: &[u8], _strategy: Strategy) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// A format in the registry
+#[derive(Clone, Copy)]
+pub struct Type(&'static dyn CompressionFormat);
+
+impl Type {
+    pub const MIO0: Type = Type(&nintendo::Mio0);
+    pub const YAZ0: Type = Type(&nintendo::Yaz0);
+    pub const YAY0: Type = Type(&nintendo::Yay0);
+    pub const YAY0_VARIANT: Type = Type(&nintendo::Yay0Variant);
+    pub const ZLIB: Type = Type(&deflate::Zlib);
+    pub const GZIP: Type = Type(&deflate::Gzip);
+    pub const RARE: Type = Type(&deflate::Rare);
+    pub const HUDSON: Type = Type(&hudson::HudsonLzss);
+    pub const KONAMI: Type = Type(&konami::Lzkn64);
+
+    /// Every known format, in the order they are reported
+    pub const ALL: &'static [Type] = &[
+        Type::MIO0,
+        Type::YAZ0,
+        Type::YAY0,
+        Type::YAY0_VARIANT,
+        Type::ZLIB,
+        Type::GZIP,
+        Type::RARE,
+        Type::HUDSON,
+        Type::KONAMI,
+    ];
+
+    /// Decode a segment that starts at the start of `bytes`, rejecting it if it fails validation
+    pub fn decode(&self, bytes: &[u8]) -> Result<Decoded, DecodeError> {
+        let decoded = self.decompress(bytes)?;
+        if self.validate(&decoded) {
+            Ok(decoded)
+        } else {
+            Err(DecodeError::Implausible)
+        }
+    }
+}
+
+impl Deref for Type {
+    type Target = dyn CompressionFormat;
+
+    fn deref(&self) -> &Self::Target {
+        self.0
+    }
+}
+
+impl PartialEq for Type {
+    fn eq(&self, other: &Self) -> bool {
+        self.name() == other.name()
+    }
+}
+
+impl Eq for Type {}
+
+impl Debug for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+pub(crate) fn read_byte(bytes: &[u8], pos: &mut usize, end: usize) -> Result<u8, DecodeError> {
+    if *pos >= end {
+        return Err(DecodeError::Truncated);
+    }
+    let b = bytes[*pos];
+    *pos += 1;
+    Ok(b)
+}
+
+pub(crate) fn read_half(bytes: &[u8], pos: &mut usize, end: usize) -> Result<u16, DecodeError> {
+    let hi = read_byte(bytes, pos, end)?;
+    let lo = read_byte(bytes, pos, end)?;
+    Ok(u16::from_be_bytes([hi, lo]))
+}
+
+/// A decompressed size read from a header, if it is possible
+pub(crate) fn check_size(size: usize) -> Result<usize, DecodeError> {
+    if size == 0 || size > MAX_DECOMPRESSED_SIZE {
+        return Err(DecodeError::BadHeader);
+    }
+    Ok(size)
+}
+
+/// Copy `len` bytes from `dist` bytes back in the output, stopping at `size`
+pub(crate) fn copy_back(
+    out: &mut Vec<u8>,
+    dist: usize,
+    len: usize,
+    size: usize,
+) -> Result<(), DecodeError> {
+    if dist == 0 || dist > out.len() {
+        return Err(DecodeError::BadReference);
+    }
+    let start = out.len() - dist;
+    for i in 0..len.min(size.saturating_sub(out.len())) {
+        out.push(out[start + i]);
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct CompressedSegment {
+    algorithm: Type,
+    rom_start: usize,
+    rom_end: usize,
+    decompressed_size: usize,
+}
+
+impl CompressedSegment {
+    pub fn algorithm(&self) -> Type {
+        self.algorithm
+    }
+    pub fn rom_start(&self) -> usize {
+        self.rom_start
+    }
+    /// End of the compressed data, not including any padding after it
+    pub fn rom_end(&self) -> usize {
+        self.rom_end
+    }
+    pub fn compressed_size(&self) -> usize {
+        self.rom_end - self.rom_start
+    }
+    pub fn decompressed_size(&self) -> usize {
+        self.decompressed_size
+    }
+    /// Compressed size as a fraction of the decompressed size
+    pub fn ratio(&self) -> f64 {
+        self.compressed_size() as f64 / self.decompressed_size as f64
+    }
+
+    /// Decode the segment again, from the rom it was found in
+    pub fn decompress(&self, rom_bytes: &[u8]) -> Result<Vec<u8>, DecodeError> {
+        self.algorithm
+            .decode(&rom_bytes[self.rom_start..])
+            .map(|decoded| decoded.data)
+    }
+}
+
+/// The segment in `algorithm` at `rom_addr`, if it is detected and decodes successfully
+fn segment_at(rom_bytes: &[u8], rom_addr: usize, algorithm: Type) -> Option<CompressedSegment> {
+    if !algorithm.detect(&rom_bytes[rom_addr..]) {
+        return None;
+    }
+    // Only count it if the header is sane and the data decodes within the rom
+    let decoded = algorithm.decode(&rom_bytes[rom_addr..]).ok()?;
+    Some(CompressedSegment {
+        algorithm,
+        rom_start: rom_addr,
+        rom_end: rom_addr + decoded.compressed_size,
+        decompressed_size: decoded.data.len(),
+    })
+}
+
+/// The signatures of every format in `Type::ALL`, as registered with the shared scanner
+#[derive(Debug)]
+pub struct Signatures(Vec<(Type, PatternId)>);
+
+impl Signatures {
+    pub(crate) fn register(builder: &mut ScannerBuilder) -> Self {
+        Self(
+            Type::ALL
+                .iter()
+                .flat_map(|&algorithm| {
+                    algorithm
+                        .signatures()
+                        .iter()
+                        .map(move |signature| (algorithm, signature))
+                })
+                .map(|(algorithm, signature)| (algorithm, builder.register(signature, WORD_SIZE)))
+                .collect(),
+        )
+    }
+}
+
+/// Find every segment in any of `formats` from `search_start` onwards, grouped by format in the order given and sorted
+/// by address within each. The signatures of every format are found in one scan, and the formats without any are
+/// checked at every word in one more pass.
+fn find_formats(rom_bytes: &[u8], search_start: usize, formats: &[Type]) -> Vec<CompressedSegment> {
+    // As a file must at least start on 4, we can limit the search to multiples of 4
+    // TODO: possibly up this to 0x10
+    let search = round_up(search_start, WORD_SIZE)..rom_bytes.len();
+
+    let shared = scan::shared();
+    let results = shared.scanner.scan(rom_bytes, search.clone());
+    let mut found = shared
+        .compression
+        .0
+        .par_iter()
+        .filter(|(algorithm, _)| formats.contains(algorithm))
+        .flat_map_iter(|&(algorithm, pattern)| {
+            results
+                .hits(pattern)
+                .iter()
+                .filter_map(move |&x| segment_at(rom_bytes, x, algorithm))
+        })
+        .collect::<Vec<_>>();
+
+    let structural = formats
+        .iter()
+        .copied()
+        .filter(|algorithm| algorithm.signatures().is_empty())
+        .collect::<Vec<_>>();
+    if !structural.is_empty() {
+        found.par_extend(search.into_par_iter().step_by(WORD_SIZE).flat_map_iter(|x| {
+            structural
+                .iter()
+                .filter_map(move |&algorithm| segment_at(rom_bytes, x, algorithm))
+        }));
+    }
+
+    let order = |algorithm: Type| formats.iter().position(|&format| format == algorithm);
+    found.sort_unstable_by_key(|seg| (order(seg.algorithm), seg.rom_start));
+    // Two signatures of the same format can both match at one address
+    found.dedup_by_key(|seg| (seg.algorithm, seg.rom_start));
+
+    found
+}
+
+/// Find every segment in `algorithm` from `search_start` onwards that is detected and decodes successfully
+pub fn find_format(rom_bytes: &[u8], search_start: usize, algorithm: Type) -> Vec<CompressedSegment> {
+    find_formats(rom_bytes, search_start, &[algorithm])
+}
+
+/// Find segments of every known format from `search_start` to the end of `rom_bytes`, grouped by format in the order of
+/// `Type::ALL`. Formats without a magic number are only included if `magicless` is set. A segment that starts inside
+/// another one is a false positive in its compressed data, so is dropped, as is any segment found at the same address
+/// as one of an earlier format.
+pub fn find_all(rom_bytes: &[u8], search_start: usize, magicless: bool) -> Vec<CompressedSegment> {
+    let formats = Type::ALL
+        .iter()
+        .copied()
+        .filter(|algorithm| magicless || algorithm.has_magic())
+        .collect::<Vec<_>>();
+    let found = find_formats(rom_bytes, search_start, &formats);
+
+    // Stable, so an earlier format wins a tie
+    let mut extents = found
+        .iter()
+        .enumerate()
+        .map(|(i, seg)| (seg.rom_start, seg.rom_end, i))
+        .collect::<Vec<_>>();
+    extents.sort_by_key(|&(start, _, _)| start);
+    let mut nested = HashSet::new();
+    let mut covered_until = 0;
+    for (start, end, i) in extents {
+        if start < covered_until {
+            nested.insert(i);
+        } else {
+            covered_until = end;
+        }
+    }
+
+    found
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| !nested.contains(i))
+        .map(|(_, seg)| seg)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_format_rejects_a_short_header() {
+        for algorithm in Type::ALL {
+            let mut bytes = algorithm.signatures().first().copied().unwrap_or_default().to_vec();
+            bytes.resize(0x10, 0);
+            for len in 0..bytes.len() {
+                assert!(algorithm.decompress(&bytes[..len]).is_err(), "{} {} bytes", algorithm, len);
+            }
+        }
+    }
+}
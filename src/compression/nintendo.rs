@@ -0,0 +1,375 @@
+//! Nintendo's Yaz0, Yay0 and MIO0, which share a 16-byte header: magic, decompressed size, then two words that are
+//! offsets for Yay0 and MIO0 and unused for Yaz0.
+
+use super::encode::{tokenise, MatchLimits, Strategy, Token};
+use super::{check_size, copy_back, read_byte, read_half, CompressionFormat, Decoded, DecodeError};
+use crate::utils::*;
+use crate::WORD_SIZE;
+
+const HEADER_SIZE: usize = 0x10;
+
+const YAZ0_MAGIC: &[u8] = b"Yaz0";
+const YAY0_MAGIC: &[u8] = b"Yay0";
+const MIO0_MAGIC: &[u8] = b"MIO0";
+
+/// Back-references of Yaz0 and Yay0: up to 0x1000 back, and 2 bytes, or 3 if longer than 0x11. Each token also costs
+/// a flag bit.
+const LONG_LIMITS: MatchLimits = MatchLimits {
+    max_dist: 0x1000,
+    min_len: 3,
+    max_len: 0x111,
+    short_len: 0x11,
+    short_cost: 17,
+    long_cost: 25,
+    literal_cost: 9,
+};
+
+/// Back-references of MIO0, which are always 2 bytes
+const MIO0_LIMITS: MatchLimits = MatchLimits {
+    max_dist: 0x1000,
+    min_len: 3,
+    max_len: 0x12,
+    short_len: 0x12,
+    short_cost: 17,
+    long_cost: 17,
+    literal_cost: 9,
+};
+
+/// The decompressed size from the header
+fn decompressed_size(bytes: &[u8]) -> Result<usize, DecodeError> {
+    if bytes.len() < HEADER_SIZE {
+        return Err(DecodeError::Truncated);
+    }
+    check_size(read_be_word(&bytes[WORD_SIZE..]) as usize)
+}
+
+/// Yaz0 interleaves the flag bytes, literals and back-references in one stream
+fn decode_yaz0(bytes: &[u8]) -> Result<Decoded, DecodeError> {
+    let size = decompressed_size(bytes)?;
+    let end = bytes.len();
+    let mut out = Vec::with_capacity(size);
+    let mut pos = HEADER_SIZE;
+
+    while out.len() < size {
+        let flags = read_byte(bytes, &mut pos, end)?;
+        for bit in (0..8).rev() {
+            if out.len() >= size {
+                break;
+            }
+            if flags & (1 << bit) != 0 {
+                out.push(read_byte(bytes, &mut pos, end)?);
+            } else {
+                let b1 = read_byte(bytes, &mut pos, end)? as usize;
+                let b2 = read_byte(bytes, &mut pos, end)? as usize;
+                let dist = ((b1 & 0xF) << 8 | b2) + 1;
+                let len = match b1 >> 4 {
+                    0 => read_byte(bytes, &mut pos, end)? as usize + 0x12,
+                    n => n + 2,
+                };
+                copy_back(&mut out, dist, len, size)?;
+            }
+        }
+    }
+
+    Ok(Decoded {
+        data: out,
+        compressed_size: pos,
+    })
+}
+
+fn encode_header(magic: &[u8], size: usize, link_offset: usize, chunk_offset: usize) -> Vec<u8> {
+    let mut out = magic.to_vec();
+    for word in [size, link_offset, chunk_offset] {
+        out.extend_from_slice(&(word as u32).to_be_bytes());
+    }
+    out
+}
+
+fn encode_yaz0(data: &[u8], strategy: Strategy) -> Vec<u8> {
+    let mut out = encode_header(YAZ0_MAGIC, data.len(), 0, 0);
+
+    for group in tokenise(data, LONG_LIMITS, strategy).chunks(8) {
+        let flags_pos = out.len();
+        out.push(0);
+        for (i, token) in group.iter().enumerate() {
+            match *token {
+                Token::Literal(b) => {
+                    out[flags_pos] |= 0x80 >> i;
+                    out.push(b);
+                }
+                Token::Match { dist, len } => {
+                    let dist = dist - 1;
+                    if len <= 0x11 {
+                        out.push(((len - 2) << 4 | dist >> 8) as u8);
+                        out.push(dist as u8);
+                    } else {
+                        out.push((dist >> 8) as u8);
+                        out.push(dist as u8);
+                        out.push((len - 0x12) as u8);
+                    }
+                }
+            }
+        }
+    }
+
+    out
+}
+
+/// Encode the three tables of Yay0 or MIO0, each straight after the previous one
+fn encode_split(data: &[u8], magic: &[u8], long_references: bool, strategy: Strategy) -> Vec<u8> {
+    let limits = if long_references {
+        LONG_LIMITS
+    } else {
+        MIO0_LIMITS
+    };
+    let mut flags = Vec::new();
+    let mut links = Vec::new();
+    let mut chunks = Vec::new();
+
+    for group in tokenise(data, limits, strategy).chunks(32) {
+        let mut word = 0u32;
+        for (i, token) in group.iter().enumerate() {
+            match *token {
+                Token::Literal(b) => {
+                    word |= 0x80000000 >> i;
+                    chunks.push(b);
+                }
+                Token::Match { dist, len } => {
+                    let dist = dist - 1;
+                    let link = match len {
+                        _ if !long_references => (len - 3) << 12 | dist,
+                        0..=0x11 => (len - 2) << 12 | dist,
+                        _ => {
+                            chunks.push((len - 0x12) as u8);
+                            dist
+                        }
+                    };
+                    links.extend_from_slice(&(link as u16).to_be_bytes());
+                }
+            }
+        }
+        flags.extend_from_slice(&word.to_be_bytes());
+    }
+
+    let link_offset = HEADER_SIZE + flags.len();
+    let chunk_offset = link_offset + links.len();
+    let mut out = encode_header(magic, data.len(), link_offset, chunk_offset);
+    out.append(&mut flags);
+    out.append(&mut links);
+    out.append(&mut chunks);
+    out
+}
+
+/// Where a Yay0/MIO0 decoder stopped reading each of its tables
+struct SplitTables {
+    flag_end: usize,
+    link_offset: usize,
+    link_end: usize,
+    chunk_offset: usize,
+}
+
+/// Yay0 and MIO0 keep the flag words, back-references and literals in three separate tables, the latter two at the
+/// offsets in the header. They differ only in that Yay0 can extend a back-reference's length with a literal byte.
+fn decode_split(bytes: &[u8], long_references: bool) -> Result<(Decoded, SplitTables), DecodeError> {
+    let size = decompressed_size(bytes)?;
+    let link_offset = read_be_word(&bytes[2 * WORD_SIZE..]) as usize;
+    let chunk_offset = read_be_word(&bytes[3 * WORD_SIZE..]) as usize;
+    if link_offset < HEADER_SIZE || chunk_offset < link_offset {
+        return Err(DecodeError::BadHeader);
+    }
+    if chunk_offset > bytes.len() {
+        return Err(DecodeError::Truncated);
+    }
+
+    let mut out = Vec::with_capacity(size);
+    let mut flag_pos = HEADER_SIZE;
+    let mut link_pos = link_offset;
+    let mut chunk_pos = chunk_offset;
+    let mut flags = 0u32;
+    let mut flags_left = 0;
+
+    while out.len() < size {
+        if flags_left == 0 {
+            if flag_pos + WORD_SIZE > link_offset {
+                return Err(DecodeError::Truncated);
+            }
+            flags = read_be_word(&bytes[flag_pos..]);
+            flag_pos += WORD_SIZE;
+            flags_left = 32;
+        }
+
+        if flags & 0x80000000 != 0 {
+            out.push(read_byte(bytes, &mut chunk_pos, bytes.len())?);
+        } else {
+            let link = read_half(bytes, &mut link_pos, chunk_offset)? as usize;
+            let dist = (link & 0xFFF) + 1;
+            let len = match link >> 12 {
+                0 if long_references => {
+                    read_byte(bytes, &mut chunk_pos, bytes.len())? as usize + 0x12
+                }
+                n if long_references => n + 2,
+                n => n + 3,
+            };
+            copy_back(&mut out, dist, len, size)?;
+        }
+        flags <<= 1;
+        flags_left -= 1;
+    }
+
+    let tables = SplitTables {
+        flag_end: flag_pos,
+        link_offset,
+        link_end: link_pos,
+        chunk_offset,
+    };
+    Ok((
+        Decoded {
+            data: out,
+            compressed_size: link_pos.max(chunk_pos),
+        },
+        tables,
+    ))
+}
+
+pub struct Yaz0;
+
+impl CompressionFormat for Yaz0 {
+    fn name(&self) -> &'static str {
+        "Yaz0"
+    }
+    fn signatures(&self) -> &'static [&'static [u8]] {
+        &[YAZ0_MAGIC]
+    }
+    fn detect(&self, bytes: &[u8]) -> bool {
+        bytes.starts_with(YAZ0_MAGIC)
+    }
+    fn decompress(&self, bytes: &[u8]) -> Result<Decoded, DecodeError> {
+        decode_yaz0(bytes)
+    }
+    fn splat_type(&self) -> Option<&'static str> {
+        Some("yaz0")
+    }
+    fn encode(&self, data: &[u8], strategy: Strategy) -> Option<Vec<u8>> {
+        Some(encode_yaz0(data, strategy))
+    }
+}
+
+pub struct Yay0;
+
+impl CompressionFormat for Yay0 {
+    fn name(&self) -> &'static str {
+        "Yay0"
+    }
+    fn signatures(&self) -> &'static [&'static [u8]] {
+        &[YAY0_MAGIC]
+    }
+    fn detect(&self, bytes: &[u8]) -> bool {
+        bytes.starts_with(YAY0_MAGIC)
+    }
+    fn decompress(&self, bytes: &[u8]) -> Result<Decoded, DecodeError> {
+        decode_split(bytes, true).map(|(decoded, _)| decoded)
+    }
+    fn splat_type(&self) -> Option<&'static str> {
+        Some("yay0")
+    }
+    fn encode(&self, data: &[u8], strategy: Strategy) -> Option<Vec<u8>> {
+        Some(encode_split(data, YAY0_MAGIC, true, strategy))
+    }
+}
+
+pub struct Mio0;
+
+impl CompressionFormat for Mio0 {
+    fn name(&self) -> &'static str {
+        "MIO0"
+    }
+    fn signatures(&self) -> &'static [&'static [u8]] {
+        &[MIO0_MAGIC]
+    }
+    fn detect(&self, bytes: &[u8]) -> bool {
+        bytes.starts_with(MIO0_MAGIC)
+    }
+    fn decompress(&self, bytes: &[u8]) -> Result<Decoded, DecodeError> {
+        decode_split(bytes, false).map(|(decoded, _)| decoded)
+    }
+    fn splat_type(&self) -> Option<&'static str> {
+        Some("mio0")
+    }
+    fn encode(&self, data: &[u8], strategy: Strategy) -> Option<Vec<u8>> {
+        Some(encode_split(data, MIO0_MAGIC, false, strategy))
+    }
+}
+
+/// The Yay0 layout under some other magic, as some developers renamed it. Since the magic can be anything, it is
+/// recognised by its structure instead: the flag words must end where the back-references start, and the
+/// back-references where the literals start (allowing for padding to a word).
+pub struct Yay0Variant;
+
+impl CompressionFormat for Yay0Variant {
+    fn name(&self) -> &'static str {
+        "Yay0-variant"
+    }
+    fn has_magic(&self) -> bool {
+        // Not one that is known in advance, so it is as prone to false positives as the formats with none
+        false
+    }
+    fn detect(&self, bytes: &[u8]) -> bool {
+        // Magic numbers are printable, which rules out most words cheaply
+        bytes.len() >= HEADER_SIZE
+            && bytes[..WORD_SIZE].iter().all(u8::is_ascii_alphanumeric)
+            && ![YAZ0_MAGIC, YAY0_MAGIC, MIO0_MAGIC].contains(&&bytes[..WORD_SIZE])
+            && (read_be_word(&bytes[2 * WORD_SIZE..]) as usize).is_multiple_of(WORD_SIZE)
+    }
+    fn decompress(&self, bytes: &[u8]) -> Result<Decoded, DecodeError> {
+        let (decoded, tables) = decode_split(bytes, true)?;
+        if tables.flag_end != tables.link_offset
+            || tables.chunk_offset - tables.link_end >= WORD_SIZE
+        {
+            return Err(DecodeError::Implausible);
+        }
+        Ok(decoded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `abc` as literals, then a reference 3 bytes back for 6 more
+    const YAZ0_ABC: &[u8] = b"Yaz0\0\0\0\x09\0\0\0\0\0\0\0\0\xE0abc\x40\x02";
+    const MIO0_ABC: &[u8] = b"MIO0\0\0\0\x09\0\0\0\x14\0\0\0\x16\xE0\0\0\0\x30\x02abc";
+
+    #[test]
+    fn decodes_hand_built_segments() {
+        for (algorithm, bytes) in [(&Yaz0 as &dyn CompressionFormat, YAZ0_ABC), (&Mio0, MIO0_ABC)] {
+            let decoded = algorithm.decompress(bytes).unwrap();
+            assert_eq!(decoded.data, b"abcabcabc", "{}", algorithm.name());
+            assert_eq!(decoded.compressed_size, bytes.len(), "{}", algorithm.name());
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        for (algorithm, bytes) in [(&Yaz0 as &dyn CompressionFormat, YAZ0_ABC), (&Mio0, MIO0_ABC)] {
+            for len in 0..bytes.len() {
+                assert!(algorithm.decompress(&bytes[..len]).is_err(), "{} {} bytes", algorithm.name(), len);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        let empty = b"Yaz0\0\0\0\0\0\0\0\0\0\0\0\0\xFF";
+        assert_eq!(Yaz0.decompress(empty).unwrap_err(), DecodeError::BadHeader);
+        let too_big = b"Yay0\x01\0\0\0\0\0\0\x14\0\0\0\x14\xFF\xFF\xFF\xFF";
+        assert_eq!(Yay0.decompress(too_big).unwrap_err(), DecodeError::BadHeader);
+        let links_in_header = b"MIO0\0\0\0\x09\0\0\0\x08\0\0\0\x16\xE0\0\0\0\x30\x02abc";
+        assert_eq!(Mio0.decompress(links_in_header).unwrap_err(), DecodeError::BadHeader);
+    }
+
+    #[test]
+    fn rejects_references_before_the_start() {
+        let bytes = b"Yaz0\0\0\0\x09\0\0\0\0\0\0\0\0\0\x40\x02";
+        assert_eq!(Yaz0.decompress(bytes).unwrap_err(), DecodeError::BadReference);
+    }
+}
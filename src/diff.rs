@@ -0,0 +1,371 @@
+//! Compares the analyses of two roms, e.g. two revisions or regional releases of the same game, by aligning their code
+//! regions and compressed segments.
+
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
+
+use rustc_hash::FxHasher;
+use similar::{Algorithm, DiffTag};
+
+use crate::compression::{self, CompressedSegment};
+use crate::findcode::RomRegion;
+use crate::utils::*;
+use crate::{RomReport, INSTRUCTION_SIZE};
+
+/// Similarity below which two regions between matching neighbours are treated as unrelated
+pub const MIN_SIMILARITY: f64 = 0.5;
+/// How long to spend diffing one pair of regions before settling for an approximate result
+const DIFF_DEADLINE: Duration = Duration::from_secs(1);
+
+/// Mask out the parts of an instruction that a relocation would change: `j`/`jal` targets, `lui`/`addiu` immediates,
+/// and the `%lo` offsets of loads and stores that aren't relative to the stack pointer.
+pub fn normalise(word: u32) -> u32 {
+    const SP: u32 = 29;
+
+    let opcode = word >> 26;
+    let rs = (word >> 21) & 0x1F;
+    match opcode {
+        // j, jal
+        0x02 | 0x03 => word & 0xFC000000,
+        // addiu, lui
+        0x09 | 0x0F => word & 0xFFFF0000,
+        // loads and stores, including cop1 and 64-bit ones
+        0x20..=0x2F | 0x31 | 0x35 | 0x37 | 0x39 | 0x3D | 0x3F if rs != SP => {
+            word & 0xFFFF0000
+        }
+        _ => word,
+    }
+}
+
+fn normalised_instructions(rom_bytes: &[u8], region: &RomRegion) -> Vec<u32> {
+    rom_bytes[region.rom_start()..region.rom_end()]
+        .chunks_exact(INSTRUCTION_SIZE)
+        .map(|v| normalise(read_be_word(v)))
+        .collect()
+}
+
+fn fingerprint<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = FxHasher::default();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Fraction of instructions the two sequences have in common, in order: twice the number of matching instructions over
+/// the total number of instructions
+pub fn similarity(a: &[u32], b: &[u32]) -> f64 {
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+
+    let ops = similar::capture_diff_slices_deadline(
+        Algorithm::Myers,
+        a,
+        b,
+        Some(Instant::now() + DIFF_DEADLINE),
+    );
+    let matching: usize = ops
+        .iter()
+        .map(|op| op.as_tag_tuple())
+        .filter(|(tag, _, _)| *tag == DiffTag::Equal)
+        .map(|(_, old, _)| old.len())
+        .sum();
+
+    2.0 * matching as f64 / (a.len() + b.len()) as f64
+}
+
+/// The best similarity two sequences of these lengths could have
+fn similarity_bound(a_len: usize, b_len: usize) -> f64 {
+    if a_len + b_len == 0 {
+        1.0
+    } else {
+        2.0 * a_len.min(b_len) as f64 / (a_len + b_len) as f64
+    }
+}
+
+/// Pair up the items of two ordered lists. Items that are `same` are matched first, as the longest common subsequence;
+/// then the items between two matches are paired in order with the best-scoring candidate whose score is at least
+/// `min_score`, the nearest one if there is a tie. Anything left over appears only in one list. Returns the indices of
+/// each pair, or of an unpaired item, with the score of the pair (1 for identical items).
+fn align<A, B>(
+    a: &[A],
+    b: &[B],
+    same: impl Fn(&A, &B) -> bool,
+    score: impl Fn(&A, &B) -> Option<f64>,
+    min_score: f64,
+) -> Vec<(Option<usize>, Option<usize>, Option<f64>)> {
+    let (n, m) = (a.len(), b.len());
+
+    // Longest common subsequence of identical items
+    let mut lcs = vec![0u32; (n + 1) * (m + 1)];
+    let at = |i: usize, j: usize| i * (m + 1) + j;
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[at(i, j)] = if same(&a[i], &b[j]) {
+                lcs[at(i + 1, j + 1)] + 1
+            } else {
+                lcs[at(i + 1, j)].max(lcs[at(i, j + 1)])
+            };
+        }
+    }
+    let mut anchors = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if same(&a[i], &b[j]) {
+            anchors.push((i, j));
+            i += 1;
+            j += 1;
+        } else if lcs[at(i + 1, j)] >= lcs[at(i, j + 1)] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    anchors.push((n, m));
+
+    let mut pairs = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    for (anchor_i, anchor_j) in anchors {
+        // Pair the leftovers between the previous anchor and this one
+        while i < anchor_i {
+            let best = (j..anchor_j)
+                .filter_map(|k| score(&a[i], &b[k]).map(|s| (k, s)))
+                .filter(|&(_, s)| s >= min_score)
+                // The nearest wins a tie, so that leftovers with equal scores are paired in order
+                .min_by(|x, y| y.1.total_cmp(&x.1).then(x.0.cmp(&y.0)));
+            match best {
+                Some((k, s)) => {
+                    pairs.extend((j..k).map(|k| (None, Some(k), None)));
+                    pairs.push((Some(i), Some(k), Some(s)));
+                    j = k + 1;
+                }
+                None => pairs.push((Some(i), None, None)),
+            }
+            i += 1;
+        }
+        pairs.extend((j..anchor_j).map(|k| (None, Some(k), None)));
+
+        if anchor_i < n {
+            pairs.push((Some(anchor_i), Some(anchor_j), Some(1.0)));
+        }
+        i = anchor_i + 1;
+        j = anchor_j + 1;
+    }
+
+    pairs
+}
+
+/// A code region in either or both roms. `similarity` is `None` unless it is in both.
+#[derive(Debug, Clone)]
+pub struct RegionDiff {
+    pub a: Option<RomRegion>,
+    pub b: Option<RomRegion>,
+    pub similarity: Option<f64>,
+}
+
+impl RegionDiff {
+    pub fn moved(&self) -> bool {
+        matches!((&self.a, &self.b), (Some(a), Some(b)) if a.rom_start() != b.rom_start())
+    }
+
+    /// Change in size from the first rom to the second, if the region is in both
+    pub fn size_change(&self) -> Option<isize> {
+        let size = |region: &RomRegion| (region.rom_end() - region.rom_start()) as isize;
+        Some(size(self.b.as_ref()?) - size(self.a.as_ref()?))
+    }
+
+    /// What happened to the region, e.g. `["moved", "grew"]`
+    pub fn changes(&self) -> Vec<&'static str> {
+        match (&self.a, &self.b) {
+            (Some(_), None) => return vec!["vanished"],
+            (None, Some(_)) => return vec!["appeared"],
+            _ => {}
+        }
+
+        let mut changes = Vec::new();
+        if self.moved() {
+            changes.push("moved");
+        }
+        match self.size_change() {
+            Some(change) if change > 0 => changes.push("grew"),
+            Some(change) if change < 0 => changes.push("shrank"),
+            _ if self.similarity.is_some_and(|s| s < 1.0) => changes.push("changed"),
+            _ => {}
+        }
+        if changes.is_empty() {
+            changes.push("unchanged");
+        }
+        changes
+    }
+}
+
+/// A compressed segment in either or both roms
+#[derive(Debug, Clone)]
+pub struct SegmentDiff {
+    pub algorithm: compression::Type,
+    pub a: Option<usize>,
+    pub b: Option<usize>,
+    /// Decompressed size in each rom
+    pub a_size: Option<usize>,
+    pub b_size: Option<usize>,
+    /// Whether the compressed data is identical
+    pub identical: bool,
+}
+
+impl SegmentDiff {
+    /// What happened to the segment, e.g. `["moved"]`
+    pub fn changes(&self) -> Vec<&'static str> {
+        match (self.a, self.b) {
+            (Some(_), None) => return vec!["vanished"],
+            (None, Some(_)) => return vec!["appeared"],
+            _ => {}
+        }
+
+        let mut changes = Vec::new();
+        if self.a != self.b {
+            changes.push("moved");
+        }
+        match (self.a_size, self.b_size) {
+            (Some(a), Some(b)) if b > a => changes.push("grew"),
+            (Some(a), Some(b)) if b < a => changes.push("shrank"),
+            _ if !self.identical => changes.push("changed"),
+            _ => {}
+        }
+        if changes.is_empty() {
+            changes.push("unchanged");
+        }
+        changes
+    }
+}
+
+#[derive(Debug)]
+pub struct RomDiff {
+    pub a: RomReport,
+    pub b: RomReport,
+    /// Every code region of both roms, in order
+    pub regions: Vec<RegionDiff>,
+    /// Every compressed segment of both roms, in order
+    pub compressed_segments: Vec<SegmentDiff>,
+}
+
+struct SegmentKey {
+    algorithm: compression::Type,
+    decompressed_size: usize,
+    fingerprint: u64,
+}
+
+fn segment_key(rom_bytes: &[u8], seg: &CompressedSegment) -> SegmentKey {
+    SegmentKey {
+        algorithm: seg.algorithm(),
+        decompressed_size: seg.decompressed_size(),
+        fingerprint: fingerprint(&rom_bytes[seg.rom_start()..seg.rom_end()]),
+    }
+}
+
+/// Align the code regions and compressed segments of two analysed roms. Both reports should have been produced with
+/// the same options from `a_bytes` and `b_bytes` respectively.
+pub fn diff(a_bytes: &[u8], a: RomReport, b_bytes: &[u8], b: RomReport) -> RomDiff {
+    let a_regions = a.code_regions.clone().unwrap_or_default();
+    let b_regions = b.code_regions.clone().unwrap_or_default();
+    let a_instrs = a_regions
+        .iter()
+        .map(|region| normalised_instructions(a_bytes, region))
+        .collect::<Vec<_>>();
+    let b_instrs = b_regions
+        .iter()
+        .map(|region| normalised_instructions(b_bytes, region))
+        .collect::<Vec<_>>();
+    let a_prints = a_instrs.iter().map(fingerprint).collect::<Vec<_>>();
+    let b_prints = b_instrs.iter().map(fingerprint).collect::<Vec<_>>();
+
+    let index_a = (0..a_regions.len()).collect::<Vec<_>>();
+    let index_b = (0..b_regions.len()).collect::<Vec<_>>();
+    let regions = align(
+        &index_a,
+        &index_b,
+        |&i, &j| a_prints[i] == b_prints[j] && a_instrs[i] == b_instrs[j],
+        |&i, &j| {
+            (similarity_bound(a_instrs[i].len(), b_instrs[j].len()) >= MIN_SIMILARITY)
+                .then(|| similarity(&a_instrs[i], &b_instrs[j]))
+        },
+        MIN_SIMILARITY,
+    )
+    .into_iter()
+    .map(|(i, j, similarity)| RegionDiff {
+        a: i.map(|i| a_regions[i].clone()),
+        b: j.map(|j| b_regions[j].clone()),
+        similarity,
+    })
+    .collect();
+
+    let a_segments = a.compressed_segments.clone().unwrap_or_default();
+    let b_segments = b.compressed_segments.clone().unwrap_or_default();
+    let mut a_segments_sorted = a_segments.iter().collect::<Vec<_>>();
+    let mut b_segments_sorted = b_segments.iter().collect::<Vec<_>>();
+    a_segments_sorted.sort_by_key(|seg| seg.rom_start());
+    b_segments_sorted.sort_by_key(|seg| seg.rom_start());
+    let a_keys = a_segments_sorted
+        .iter()
+        .map(|seg| segment_key(a_bytes, seg))
+        .collect::<Vec<_>>();
+    let b_keys = b_segments_sorted
+        .iter()
+        .map(|seg| segment_key(b_bytes, seg))
+        .collect::<Vec<_>>();
+
+    let compressed_segments = align(
+        &a_keys,
+        &b_keys,
+        |x, y| {
+            x.algorithm == y.algorithm
+                && x.decompressed_size == y.decompressed_size
+                && x.fingerprint == y.fingerprint
+        },
+        |x, y| (x.algorithm == y.algorithm).then_some(1.0),
+        0.0,
+    )
+    .into_iter()
+    .map(|(i, j, _)| {
+        let key = i.map(|i| &a_keys[i]).or(j.map(|j| &b_keys[j])).unwrap();
+        SegmentDiff {
+            algorithm: key.algorithm,
+            a: i.map(|i| a_segments_sorted[i].rom_start()),
+            b: j.map(|j| b_segments_sorted[j].rom_start()),
+            a_size: i.map(|i| a_keys[i].decompressed_size),
+            b_size: j.map(|j| b_keys[j].decompressed_size),
+            identical: i
+                .zip(j)
+                .is_some_and(|(i, j)| a_keys[i].fingerprint == b_keys[j].fingerprint),
+        }
+    })
+    .collect();
+
+    RomDiff {
+        a,
+        b,
+        regions,
+        compressed_segments,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn align_pairs_changed_segments_between_anchors() {
+        // Like compressed segments of the same format, every changed item scores the same against every other
+        let a = [0, 11, 12, 3];
+        let b = [0, 21, 22, 3];
+        let pairs = align(&a, &b, |x, y| x == y, |_, _| Some(1.0), 0.0);
+        let paired = pairs.iter().map(|&(i, j, _)| (i, j)).collect::<Vec<_>>();
+        assert_eq!(
+            paired,
+            [
+                (Some(0), Some(0)),
+                (Some(1), Some(1)),
+                (Some(2), Some(2)),
+                (Some(3), Some(3)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,284 @@
+use crate::error::{AnalyserError, Result};
+use crate::utils::*;
+
+/// Size of a disk image stored in LBA order, as produced by the SDK and most dumping tools
+pub const NDD_SIZE: usize = 0x3DEC800;
+
+const SECTORS_PER_BLOCK: usize = 85;
+const DISK_TYPES: usize = 7;
+const ZONES: usize = 16;
+/// Each zone reserves this many tracks to replace defective ones, so they do not get LBAs
+const SPARE_TRACKS_PER_ZONE: usize = 12;
+const BLOCKS_PER_TRACK: usize = 2;
+
+/// Sector size of each physical zone (head 0 zones 0-7 then head 1 zones 0-7)
+const ZONE_SECTOR_SIZE: [usize; ZONES] = [
+    232, 216, 208, 192, 176, 160, 144, 128, //
+    216, 208, 192, 176, 160, 144, 128, 112,
+];
+const ZONE_TRACKS: [usize; ZONES] = [
+    158, 158, 149, 149, 149, 149, 149, 114, //
+    158, 158, 149, 149, 149, 149, 149, 114,
+];
+/// LBAs run through the physical zones in a different order for each disk type. The zones before `5 + 2 * type` are
+/// the read-only (ROM) area, the rest are writable (RAM).
+const VZONE_TO_PZONE: [[usize; ZONES]; DISK_TYPES] = [
+    [0, 1, 2, 9, 8, 3, 4, 5, 6, 7, 15, 14, 13, 12, 11, 10],
+    [0, 1, 2, 3, 10, 9, 8, 4, 5, 6, 7, 15, 14, 13, 12, 11],
+    [0, 1, 2, 3, 4, 11, 10, 9, 8, 5, 6, 7, 15, 14, 13, 12],
+    [0, 1, 2, 3, 4, 5, 12, 11, 10, 9, 8, 6, 7, 15, 14, 13],
+    [0, 1, 2, 3, 4, 5, 6, 13, 12, 11, 10, 9, 8, 7, 15, 14],
+    [0, 1, 2, 3, 4, 5, 6, 7, 14, 13, 12, 11, 10, 9, 8, 15],
+    [0, 1, 2, 3, 4, 5, 6, 7, 15, 14, 13, 12, 11, 10, 9, 8],
+];
+
+/// The system area is LBAs 0-23; the IPL is loaded from the LBA after it
+pub const SYSTEM_AREA_LBAS: u32 = 24;
+/// Retail disks keep copies of the system data in these LBAs, development disks in the next two of each
+const RETAIL_SYSTEM_LBAS: [u32; 4] = [0, 1, 8, 9];
+const DEVELOPMENT_SYSTEM_LBAS: [u32; 4] = [2, 3, 10, 11];
+
+const STANDARD_FORMAT_TYPE: u8 = 0x10;
+
+const fn zone_block_size(pzone: usize) -> usize {
+    ZONE_SECTOR_SIZE[pzone] * SECTORS_PER_BLOCK
+}
+
+const fn zone_lba_count(pzone: usize) -> usize {
+    (ZONE_TRACKS[pzone] - SPARE_TRACKS_PER_ZONE) * BLOCKS_PER_TRACK
+}
+
+/// The parts of the system data that describe the layout and boot process
+#[derive(Debug, Clone)]
+pub struct SystemArea {
+    pub region: u32,
+    pub format_type: u8,
+    pub disk_type: u8,
+    /// Number of blocks the IPL loads into RAM at boot
+    pub ipl_load_blocks: u16,
+    pub ipl_load_address: u32,
+    pub development: bool,
+}
+
+impl SystemArea {
+    fn parse(block: &[u8], development: bool) -> Option<SystemArea> {
+        let system = SystemArea {
+            region: read_be_word(&block[0x00..]),
+            format_type: block[0x04],
+            disk_type: block[0x05] & 0xF,
+            ipl_load_blocks: u16::from_be_bytes([block[0x06], block[0x07]]),
+            ipl_load_address: read_be_word(&block[0x1C..]),
+            development,
+        };
+        (system.format_type == STANDARD_FORMAT_TYPE && (system.disk_type as usize) < DISK_TYPES)
+            .then_some(system)
+    }
+
+    pub fn region_name(&self) -> &'static str {
+        match self.region {
+            0xE848D316 => "Japan",
+            0x2263EE56 => "USA",
+            0x00000000 => "Development",
+            _ => "unknown",
+        }
+    }
+}
+
+/// Maps LBAs to offsets in an LBA-ordered disk image. Every block in a zone is the same size, but the size varies by
+/// zone and the order the zones are visited in depends on the disk type.
+#[derive(Debug, Clone)]
+pub struct DiskLayout {
+    pub system: SystemArea,
+    /// Image offset of the start of each LBA, plus one past the end
+    lba_offsets: Vec<usize>,
+    rom_end_lba: u32,
+}
+
+impl DiskLayout {
+    fn new(system: SystemArea) -> DiskLayout {
+        let vzones = &VZONE_TO_PZONE[system.disk_type as usize];
+        let mut lba_offsets = vec![0];
+        let mut rom_end_lba = 0;
+
+        for (vzone, &pzone) in vzones.iter().enumerate() {
+            if vzone == 5 + 2 * system.disk_type as usize {
+                rom_end_lba = lba_offsets.len() as u32 - 1;
+            }
+            for _ in 0..zone_lba_count(pzone) {
+                lba_offsets.push(lba_offsets.last().unwrap() + zone_block_size(pzone));
+            }
+        }
+        if rom_end_lba == 0 {
+            // Type 6 is entirely ROM
+            rom_end_lba = lba_offsets.len() as u32 - 1;
+        }
+
+        DiskLayout {
+            system,
+            lba_offsets,
+            rom_end_lba,
+        }
+    }
+
+    /// Parse the system area of an LBA-ordered disk image
+    pub fn parse(disk_bytes: &[u8]) -> Result<DiskLayout> {
+        if disk_bytes.len() != NDD_SIZE {
+            return Err(AnalyserError::BadDiskImage(format!(
+                "size 0x{:X} is not that of an LBA-ordered disk image (0x{:X})",
+                disk_bytes.len(),
+                NDD_SIZE
+            )));
+        }
+
+        // The system area is all in zone 0, so its LBAs are all the same size whatever the disk type
+        let block_size = zone_block_size(0);
+        let find_system = |lbas: [u32; 4], development| {
+            lbas.iter().find_map(|&lba| {
+                let offset = lba as usize * block_size;
+                SystemArea::parse(&disk_bytes[offset..offset + block_size], development)
+            })
+        };
+
+        let system = find_system(RETAIL_SYSTEM_LBAS, false)
+            .or_else(|| find_system(DEVELOPMENT_SYSTEM_LBAS, true))
+            .ok_or_else(|| AnalyserError::BadDiskImage("no valid system data found".to_string()))?;
+
+        Ok(DiskLayout::new(system))
+    }
+
+    pub fn lba_count(&self) -> u32 {
+        self.lba_offsets.len() as u32 - 1
+    }
+
+    /// One past the last LBA of the read-only area
+    pub fn rom_end_lba(&self) -> u32 {
+        self.rom_end_lba
+    }
+
+    pub fn lba_to_offset(&self, lba: u32) -> usize {
+        self.lba_offsets[lba as usize]
+    }
+
+    /// The LBA containing an image offset and the offset within it
+    pub fn offset_to_lba(&self, offset: usize) -> (u32, usize) {
+        let lba = self.lba_offsets.partition_point(|&x| x <= offset) - 1;
+        (lba as u32, offset - self.lba_offsets[lba])
+    }
+
+    pub fn format_offset(&self, offset: usize) -> String {
+        let (lba, lba_offset) = self.offset_to_lba(offset);
+        format!("LBA {}+0x{:04X}", lba, lba_offset)
+    }
+
+    /// Image offsets of the IPL loaded at boot
+    pub fn boot_area(&self) -> std::ops::Range<usize> {
+        let end_lba = (SYSTEM_AREA_LBAS + self.system.ipl_load_blocks as u32).min(self.lba_count());
+        self.lba_to_offset(SYSTEM_AREA_LBAS)..self.lba_to_offset(end_lba)
+    }
+
+    /// Image offsets of everything after the boot area
+    pub fn data_area(&self) -> std::ops::Range<usize> {
+        self.boot_area().end..self.lba_to_offset(self.lba_count())
+    }
+
+    /// The address an image offset is loaded to, if it is in the boot area
+    pub fn boot_vram(&self, offset: usize) -> Option<u32> {
+        let boot_area = self.boot_area();
+        boot_area.contains(&offset).then(|| {
+            self.system
+                .ipl_load_address
+                .wrapping_add((offset - boot_area.start) as u32)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LBA_COUNT: u32 = 4316;
+
+    fn system(disk_type: u8, development: bool) -> SystemArea {
+        SystemArea {
+            region: 0xE848D316,
+            format_type: STANDARD_FORMAT_TYPE,
+            disk_type,
+            ipl_load_blocks: 4,
+            ipl_load_address: 0x80000400,
+            development,
+        }
+    }
+
+    /// An otherwise empty image with valid system data in one LBA
+    fn image_with_system_at(lba: u32) -> Vec<u8> {
+        let mut image = vec![0; NDD_SIZE];
+        let block = &mut image[lba as usize * zone_block_size(0)..];
+        block[0x00..0x04].copy_from_slice(&0xE848D316u32.to_be_bytes());
+        block[0x04] = STANDARD_FORMAT_TYPE;
+        block[0x05] = 3;
+        block[0x06..0x08].copy_from_slice(&4u16.to_be_bytes());
+        block[0x1C..0x20].copy_from_slice(&0x80000400u32.to_be_bytes());
+        image
+    }
+
+    #[test]
+    fn every_disk_type_fills_the_image() {
+        for disk_type in 0..DISK_TYPES as u8 {
+            let layout = DiskLayout::new(system(disk_type, false));
+            assert_eq!(layout.lba_count(), LBA_COUNT, "type {}", disk_type);
+            assert_eq!(layout.lba_to_offset(LBA_COUNT), NDD_SIZE, "type {}", disk_type);
+        }
+        assert_eq!(NDD_SIZE, 0x3DEC800);
+    }
+
+    #[test]
+    fn rom_end_lba_of_each_disk_type() {
+        // libleo's first RAM LBA of each type, which doesn't count the system area; type 6 has no RAM area
+        let ram_start = [1418, 1966, 2514, 3062, 3610, 4088, 4292];
+        for (disk_type, start) in ram_start.into_iter().enumerate() {
+            let layout = DiskLayout::new(system(disk_type as u8, false));
+            assert_eq!(layout.rom_end_lba(), start + SYSTEM_AREA_LBAS, "type {}", disk_type);
+        }
+    }
+
+    #[test]
+    fn offsets_round_trip() {
+        let layout = DiskLayout::new(system(0, false));
+        let zone0_block = zone_block_size(0);
+        assert_eq!(layout.lba_to_offset(1), zone0_block);
+        assert_eq!(layout.offset_to_lba(0), (0, 0));
+        assert_eq!(layout.offset_to_lba(zone0_block - 1), (0, zone0_block - 1));
+        assert_eq!(layout.format_offset(zone0_block + 0x10), "LBA 1+0x0010");
+
+        // Type 0's first zone change, from physical zone 0 to 1
+        let zone1_start = zone_lba_count(0) as u32;
+        let offset = layout.lba_to_offset(zone1_start);
+        assert_eq!(offset, zone_lba_count(0) * zone0_block);
+        assert_eq!(layout.offset_to_lba(offset - 1), (zone1_start - 1, zone0_block - 1));
+        assert_eq!(layout.format_offset(offset + 0x123), format!("LBA {}+0x0123", zone1_start));
+
+        for lba in [0, 23, 24, 1441, 1442, LBA_COUNT - 1] {
+            assert_eq!(layout.offset_to_lba(layout.lba_to_offset(lba)), (lba, 0));
+            assert_eq!(layout.format_offset(layout.lba_to_offset(lba)), format!("LBA {}+0x0000", lba));
+        }
+        assert_eq!(layout.offset_to_lba(NDD_SIZE - 1).0, LBA_COUNT - 1);
+    }
+
+    #[test]
+    fn system_area_is_found_in_every_copy() {
+        for (lbas, development) in [(RETAIL_SYSTEM_LBAS, false), (DEVELOPMENT_SYSTEM_LBAS, true)] {
+            for lba in lbas {
+                let layout = DiskLayout::parse(&image_with_system_at(lba)).unwrap();
+                assert_eq!(layout.system.development, development, "LBA {}", lba);
+                assert_eq!(layout.system.disk_type, 3, "LBA {}", lba);
+                assert_eq!(layout.system.ipl_load_address, 0x80000400);
+                assert_eq!(layout.boot_area(), layout.lba_to_offset(24)..layout.lba_to_offset(28));
+                assert_eq!(layout.data_area(), layout.lba_to_offset(28)..NDD_SIZE);
+            }
+        }
+
+        // Neither set of LBAs, or the wrong size
+        assert!(DiskLayout::parse(&image_with_system_at(4)).is_err());
+        assert!(DiskLayout::parse(&vec![0; NDD_SIZE - 0x10]).is_err());
+    }
+}
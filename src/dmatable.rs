@@ -0,0 +1,199 @@
+//! File tables, e.g. the `dmadata` of Ocarina of Time and Majora's Mask: arrays of entries with a start and end offset
+//! for every file in the rom. They are found by their structure, then kept only if their offsets land on the compressed
+//! segments and code regions already found, which makes each entry a real file boundary.
+
+use std::collections::HashSet;
+use std::ops::Range;
+
+use crate::compression::CompressedSegment;
+use crate::findcode::RomRegion;
+use crate::utils::*;
+use crate::WORD_SIZE;
+
+/// Fewest entries a table can have
+const MIN_ENTRIES: usize = 4;
+/// Fewest entries that must land on a known boundary
+const MIN_HITS: usize = 3;
+/// At least one in this many entries must land on a known boundary
+const HIT_RATIO: usize = 4;
+
+/// Entry sizes searched, in words
+const ENTRY_WORDS: Range<usize> = 2..5;
+
+/// Files are aligned to this, so a boundary found from the data is also looked for rounded to it
+const FILE_ALIGN: usize = 0x10;
+
+/// Where the offsets are in each entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TableLayout {
+    /// Size of an entry in bytes
+    pub entry_size: usize,
+    /// Position of the start offset in an entry; the end offset is the next word
+    pub start_offset: usize,
+}
+
+/// One entry of a table. Zelda's dmadata stores an end of 0 for a file that is not compressed, and both as
+/// `0xFFFFFFFF` for one that was removed from the rom.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableEntry {
+    File { start: usize, end: usize },
+    StartOnly { start: usize },
+    Absent,
+}
+
+impl TableEntry {
+    fn parse(bytes: &[u8]) -> Self {
+        let start = read_be_word(bytes);
+        let end = read_be_word(&bytes[WORD_SIZE..]);
+        match (start, end) {
+            (u32::MAX, u32::MAX) => TableEntry::Absent,
+            (start, 0) => TableEntry::StartOnly {
+                start: start as usize,
+            },
+            (start, end) => TableEntry::File {
+                start: start as usize,
+                end: end as usize,
+            },
+        }
+    }
+
+    pub fn start(&self) -> Option<usize> {
+        match *self {
+            TableEntry::File { start, .. } | TableEntry::StartOnly { start } => Some(start),
+            TableEntry::Absent => None,
+        }
+    }
+
+    pub fn end(&self) -> Option<usize> {
+        match *self {
+            TableEntry::File { end, .. } => Some(end),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DmaTable {
+    pub rom_start: usize,
+    pub rom_end: usize,
+    pub layout: TableLayout,
+    pub entries: Vec<TableEntry>,
+    /// Number of entries that start or end on a known boundary
+    pub hits: usize,
+}
+
+/// Read entries in `layout` from the start of `bytes` for as long as the files are in order and don't overlap
+fn read_entries(bytes: &[u8], layout: TableLayout, rom_len: usize) -> Vec<TableEntry> {
+    let mut entries = Vec::new();
+    // The first file can start at 0, e.g. Zelda's dmadata lists the header and IPL3 as a file
+    let mut next_start = 0;
+
+    for entry in bytes.chunks_exact(layout.entry_size) {
+        let entry = TableEntry::parse(&entry[layout.start_offset..]);
+        match entry {
+            TableEntry::File { start, end } => {
+                if start < next_start || end <= start || end > rom_len {
+                    break;
+                }
+                next_start = end;
+            }
+            TableEntry::StartOnly { start } => {
+                if start < next_start || start >= rom_len || (start == 0 && !entries.is_empty()) {
+                    break;
+                }
+                next_start = start + 1;
+            }
+            TableEntry::Absent => {}
+        }
+        entries.push(entry);
+    }
+
+    // Trailing removed files are indistinguishable from padding
+    while entries.last() == Some(&TableEntry::Absent) {
+        entries.pop();
+    }
+    entries
+}
+
+/// Find tables from `search_start` to the end of `rom_bytes`, outside code regions and compressed segments, whose
+/// offsets land on the start or end of enough of them. Where several layouts fit, the one with the most hits is kept.
+pub fn find_tables(
+    rom_bytes: &[u8],
+    search_start: usize,
+    regions: &[RomRegion],
+    compressed: &[CompressedSegment],
+) -> Vec<DmaTable> {
+    let mut boundaries = HashSet::new();
+    for (start, end) in regions
+        .iter()
+        .map(|region| (region.rom_start(), region.rom_end()))
+        .chain(compressed.iter().map(|seg| (seg.rom_start(), seg.rom_end())))
+    {
+        boundaries.extend([
+            start,
+            round_down(start, FILE_ALIGN),
+            end,
+            round_up(end, FILE_ALIGN),
+        ]);
+    }
+    let mut skip = regions
+        .iter()
+        .map(|region| region.rom_start()..region.rom_end())
+        .chain(compressed.iter().map(|seg| seg.rom_start()..seg.rom_end()))
+        .collect::<Vec<_>>();
+    skip.sort_by_key(|range| range.start);
+    let is_boundary = |offset: Option<usize>| offset.is_some_and(|offset| boundaries.contains(&offset));
+
+    let mut tables = Vec::new();
+    let mut pos = round_up(search_start, WORD_SIZE);
+    // Index of the first range in `skip` that doesn't end before `pos`
+    let mut next_skip = 0;
+    while pos < rom_bytes.len() {
+        while skip.get(next_skip).is_some_and(|range| range.end <= pos) {
+            next_skip += 1;
+        }
+        if let Some(range) = skip.get(next_skip).filter(|range| range.start <= pos) {
+            pos = round_up(range.end, WORD_SIZE);
+            continue;
+        }
+        // A table can't run into code or compressed data either
+        let end = skip
+            .get(next_skip)
+            .map_or(rom_bytes.len(), |range| range.start);
+
+        let best = ENTRY_WORDS
+            .flat_map(|words| {
+                (0..words - 1).map(move |word| TableLayout {
+                    entry_size: words * WORD_SIZE,
+                    start_offset: word * WORD_SIZE,
+                })
+            })
+            .filter_map(|layout| {
+                let entries = read_entries(&rom_bytes[pos..end], layout, rom_bytes.len());
+                let hits = entries
+                    .iter()
+                    .filter(|entry| is_boundary(entry.start()) || is_boundary(entry.end()))
+                    .count();
+                (entries.len() >= MIN_ENTRIES && hits >= MIN_HITS && hits * HIT_RATIO >= entries.len()).then(|| {
+                    DmaTable {
+                        rom_start: pos,
+                        rom_end: pos + entries.len() * layout.entry_size,
+                        layout,
+                        entries,
+                        hits,
+                    }
+                })
+            })
+            .max_by_key(|table| table.hits);
+
+        match best {
+            Some(table) => {
+                pos = table.rom_end;
+                tables.push(table);
+            }
+            None => pos += WORD_SIZE,
+        }
+    }
+
+    tables
+}
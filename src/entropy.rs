@@ -0,0 +1,121 @@
+//! Sliding-window Shannon entropy, to find compressed or encrypted data that has no known magic.
+
+use std::ops::Range;
+
+use rayon::prelude::*;
+
+#[derive(Debug, Clone)]
+pub struct EntropyOptions {
+    /// size of the window the entropy is measured over
+    pub window: usize,
+    /// distance the window moves each time
+    pub step: usize,
+    /// entropy in bits per byte at or above which a window counts as high-entropy
+    pub threshold: f64,
+}
+
+impl Default for EntropyOptions {
+    fn default() -> Self {
+        Self {
+            window: 0x1000,
+            step: 0x400,
+            threshold: 7.5,
+        }
+    }
+}
+
+/// A contiguous run of high-entropy windows, less anything already explained
+#[derive(Debug, Clone)]
+pub struct EntropySpan {
+    pub rom_start: usize,
+    pub rom_end: usize,
+    /// Entropy of the whole span, in bits per byte
+    pub entropy: f64,
+}
+
+/// Shannon entropy of some bytes, in bits per byte
+pub fn shannon(bytes: &[u8]) -> f64 {
+    if bytes.is_empty() {
+        return 0.0;
+    }
+
+    let mut counts = [0usize; 0x100];
+    for &b in bytes {
+        counts[b as usize] += 1;
+    }
+
+    let len = bytes.len() as f64;
+    counts
+        .iter()
+        .filter(|&&count| count != 0)
+        .map(|&count| {
+            let p = count as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Remove every range in `covered` from `span`
+fn subtract(span: Range<usize>, covered: &[Range<usize>]) -> Vec<Range<usize>> {
+    let mut pieces = vec![span];
+    for hole in covered {
+        pieces = pieces
+            .into_iter()
+            .flat_map(|piece| {
+                if hole.end <= piece.start || piece.end <= hole.start {
+                    vec![piece]
+                } else {
+                    [piece.start..hole.start, hole.end..piece.end]
+                        .into_iter()
+                        .filter(|r| !r.is_empty())
+                        .collect()
+                }
+            })
+            .collect();
+    }
+    pieces
+}
+
+/// Find the spans from `search_start` to the end of `rom_bytes` where the entropy is at least the threshold, ignoring
+/// anything in `covered` (e.g. code regions and known compressed segments). Pieces smaller than a step left over after
+/// removing the covered ranges are dropped, since they are usually just the edge of a window overlapping them.
+pub fn find_high_entropy(
+    rom_bytes: &[u8],
+    search_start: usize,
+    options: &EntropyOptions,
+    covered: &[Range<usize>],
+) -> Vec<EntropySpan> {
+    let window = options.window.max(1);
+    let step = options.step.max(1);
+    if search_start + window > rom_bytes.len() {
+        return Vec::new();
+    }
+
+    let starts = (search_start..=rom_bytes.len() - window)
+        .step_by(step)
+        .collect::<Vec<_>>();
+    let high = starts
+        .par_iter()
+        .map(|&start| shannon(&rom_bytes[start..start + window]) >= options.threshold)
+        .collect::<Vec<_>>();
+
+    // Merge runs of overlapping high-entropy windows
+    let mut spans: Vec<Range<usize>> = Vec::new();
+    for (&start, _) in starts.iter().zip(&high).filter(|(_, &high)| high) {
+        match spans.last_mut() {
+            Some(span) if start <= span.end => span.end = start + window,
+            _ => spans.push(start..start + window),
+        }
+    }
+
+    spans
+        .into_iter()
+        .flat_map(|span| subtract(span, covered))
+        .filter(|piece| piece.len() >= step)
+        .map(|piece| EntropySpan {
+            rom_start: piece.start,
+            rom_end: piece.end,
+            entropy: shannon(&rom_bytes[piece]),
+        })
+        .collect()
+}
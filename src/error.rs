@@ -0,0 +1,60 @@
+use std::fmt::Display;
+use std::io;
+
+#[derive(Debug)]
+pub enum AnalyserError {
+    Io(io::Error),
+    /// The first word is not any arrangement of the usual header magic
+    UnknownByteOrder([u8; 4]),
+    /// The size is not a multiple of the word size
+    BadSize(usize),
+    /// Too small to contain everything expected of the input
+    TruncatedRom { size: usize, expected: usize },
+    /// Not a disk image that can be analysed
+    BadDiskImage(String),
+    /// The IPL3 isn't one whose boot checksum algorithm is known
+    UnknownCic,
+    /// The output format can't be produced for this input or subcommand
+    UnsupportedOutput(&'static str),
+}
+
+impl Display for AnalyserError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AnalyserError::Io(err) => write!(f, "{}", err),
+            AnalyserError::UnknownByteOrder(magic) => write!(
+                f,
+                "unrecognised header {:02X?}, use --byte-order or --raw to analyse it anyway",
+                magic
+            ),
+            AnalyserError::BadSize(size) => {
+                write!(f, "size 0x{:X} is not a multiple of 4", size)
+            }
+            AnalyserError::TruncatedRom { size, expected } => write!(
+                f,
+                "truncated rom: size 0x{:X}, expected at least 0x{:X}",
+                size, expected
+            ),
+            AnalyserError::BadDiskImage(reason) => write!(f, "bad disk image: {}", reason),
+            AnalyserError::UnknownCic => write!(f, "checksum algorithm for this CIC is unknown"),
+            AnalyserError::UnsupportedOutput(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for AnalyserError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AnalyserError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for AnalyserError {
+    fn from(err: io::Error) -> Self {
+        AnalyserError::Io(err)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, AnalyserError>;
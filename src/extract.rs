@@ -0,0 +1,120 @@
+//! Writes compressed segments out as files, both as they are in the rom and decompressed, with a manifest describing
+//! them.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+
+use crate::compression::CompressedSegment;
+use crate::error::Result;
+
+const CRC32_ALG: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+
+/// Name of the manifest written alongside the segments
+pub const MANIFEST_NAME: &str = "manifest.json";
+/// Bumped whenever a change to the manifest would break an existing consumer; fields that are only added don't bump it
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// One segment written by `extract_all`. File names are relative to the output directory.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExtractedSegment {
+    pub algorithm: String,
+    pub rom_start: usize,
+    /// End of the compressed data, not including any padding after it
+    pub rom_end: usize,
+    pub compressed_size: usize,
+    pub decompressed_size: usize,
+    /// The compressed data exactly as it is in the rom, header included
+    pub raw_file: String,
+    pub decompressed_file: String,
+    /// CRC-32 of the raw file
+    pub raw_crc32: u32,
+    /// CRC-32 of the decompressed file
+    pub decompressed_crc32: u32,
+    /// SHA-1 of the raw file, as lowercase hex
+    pub raw_sha1: String,
+    /// SHA-1 of the decompressed file, as lowercase hex
+    pub decompressed_sha1: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Manifest<'a> {
+    format_version: u32,
+    tool_version: &'static str,
+    segments: &'a [ExtractedSegment],
+}
+
+fn sha1_hex(bytes: &[u8]) -> String {
+    Sha1::digest(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Name of the raw file for a segment, e.g. `00123450.Yaz0`; the decompressed file adds `.bin`
+pub fn file_name(segment: &CompressedSegment) -> String {
+    format!("{:08X}.{}", segment.rom_start(), segment.algorithm())
+}
+
+/// Write one segment into `dir`
+pub fn extract(rom_bytes: &[u8], segment: &CompressedSegment, dir: &Path) -> Result<ExtractedSegment> {
+    let raw = &rom_bytes[segment.rom_start()..segment.rom_end()];
+    let decompressed = segment.decompress(rom_bytes).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} segment at 0x{:X} could not be decompressed: {}",
+                segment.algorithm(),
+                segment.rom_start(),
+                err
+            ),
+        )
+    })?;
+
+    let raw_file = file_name(segment);
+    let decompressed_file = format!("{}.bin", raw_file);
+    fs::write(dir.join(&raw_file), raw)?;
+    fs::write(dir.join(&decompressed_file), &decompressed)?;
+
+    Ok(ExtractedSegment {
+        algorithm: segment.algorithm().to_string(),
+        rom_start: segment.rom_start(),
+        rom_end: segment.rom_end(),
+        compressed_size: segment.compressed_size(),
+        decompressed_size: decompressed.len(),
+        raw_file,
+        decompressed_file,
+        raw_crc32: CRC32_ALG.checksum(raw),
+        decompressed_crc32: CRC32_ALG.checksum(&decompressed),
+        raw_sha1: sha1_hex(raw),
+        decompressed_sha1: sha1_hex(&decompressed),
+    })
+}
+
+/// Write every segment into `dir`, creating it if necessary, followed by a manifest listing them in rom order
+pub fn extract_all(
+    rom_bytes: &[u8],
+    segments: &[CompressedSegment],
+    dir: &Path,
+) -> Result<Vec<ExtractedSegment>> {
+    fs::create_dir_all(dir)?;
+
+    let mut extracted = segments
+        .iter()
+        .map(|segment| extract(rom_bytes, segment, dir))
+        .collect::<Result<Vec<_>>>()?;
+    extracted.sort_by_key(|segment| segment.rom_start);
+
+    let manifest = Manifest {
+        format_version: MANIFEST_VERSION,
+        tool_version: env!("CARGO_PKG_VERSION"),
+        segments: &extracted,
+    };
+    let file = fs::File::create(dir.join(MANIFEST_NAME))?;
+    serde_json::to_writer_pretty(io::BufWriter::new(file), &manifest).map_err(io::Error::from)?;
+
+    Ok(extracted)
+}
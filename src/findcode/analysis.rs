@@ -1,3 +1,4 @@
+use super::trace::{Rejected, Rejection, Trace, TraceEvent};
 use super::RomRegion;
 // use super::
 use crate::utils::*;
@@ -302,34 +303,30 @@ fn references_uninitialized(
     false
 }
 
-// Check if this instruction is (probably) invalid when at the beginning of a region of code
-fn is_invalid_start_instruction(
+// Check if this instruction is (probably) invalid when at the beginning of a region of code, returning the rule that
+// rejected it if so
+fn start_rejection(
     my_instruction: &MyInstruction,
     gpr_reg_states: &EnumMap<MipsGpr, RegisterState>,
     fpr_reg_states: &EnumMap<MipsFpr, RegisterState>,
-) -> bool {
+) -> Option<Rejection> {
     let id = my_instruction.0.instr_id();
 
-    // println!("    {}", my_instruction.0.disassemble(None, 0) );
-
     // Check if this is a valid instruction to begin with
-    if !super::is_valid(my_instruction) {
-        // println!("Invalid instruction");
-        return true;
+    if let Some(rejection) = super::rejection(my_instruction) {
+        return Some(rejection);
     }
 
     match id {
         // Code probably won't start with a nop (some functions do, but it'll just be one nop that can be recovered later)
         rabbitizer::InstrId::cpu_nop => {
-            // println!("nop");
-            return true;
+            return Some(Rejection::StartsWithNop);
         }
 
         // Code shouldn't jump to $zero
         rabbitizer::InstrId::cpu_jr => {
             if my_instruction.rs() == MipsGpr::zero {
-                // println!("jump to $zero");
-                return true;
+                return Some(Rejection::JumpToZero);
             }
         }
 
@@ -343,23 +340,15 @@ fn is_invalid_start_instruction(
         | rabbitizer::InstrId::cpu_dsrl32
         | rabbitizer::InstrId::cpu_dsra
         | rabbitizer::InstrId::cpu_dsra32 => {
-            // println!(
-            //     "test {:?} {:?} {:?}\n",
-            //     id,
-            //     my_instruction.instr_get_rt(),
-            //     my_instruction.instr_get_sa()
-            // );
             if (my_instruction.rt() == MipsGpr::zero)
                 && (my_instruction.sa() != 0)
             {
-                // println!("Shift with $zero as input and non-zero sa");
-                return true;
+                return Some(Rejection::ShiftOfZero);
             }
         }
         // Code probably won't start with mthi or mtlo
         rabbitizer::InstrId::cpu_mthi | rabbitizer::InstrId::cpu_mtlo => {
-            // println!("starts with mthi or mtlo");
-            return true;
+            return Some(Rejection::StartsWithMthiOrMtlo);
         }
 
         // Code shouldn't start with branches based on the cop1 condition flag (it won't have been set yet)
@@ -367,36 +356,31 @@ fn is_invalid_start_instruction(
         | rabbitizer::InstrId::cpu_bc1f
         | rabbitizer::InstrId::cpu_bc1tl
         | rabbitizer::InstrId::cpu_bc1fl => {
-            // println!("branch from cop1 condition flag");
-            return true;
+            return Some(Rejection::Cop1ConditionBranch);
         }
 
         // add/sub and addi are good indicators that the bytes aren't actually instructions, since addu/subu and addiu would normally be used
         rabbitizer::InstrId::cpu_add
         | rabbitizer::InstrId::cpu_addi
         | rabbitizer::InstrId::cpu_sub => {
-            // println!("add/sub/addi");
-            return true;
+            return Some(Rejection::TrappingArithmetic);
         }
 
         _ => {}
     }
     // Code shouldn't output to $zero
     if my_instruction.has_zero_output() {
-        // println!("has zero output");
-        return true;
+        return Some(Rejection::WritesZero);
     }
 
     // Code shouldn't start with an unconditional branch
     if my_instruction.0.is_unconditional_branch() {
-        // println!("unconditional branch");
-        return true;
+        return Some(Rejection::UnconditionalBranch);
     }
 
     // Code shouldn't start with a linked jump, as it'd need to save the return address first
     if my_instruction.0.does_link() {
-        // println!("does link");
-        return true;
+        return Some(Rejection::Links);
     }
 
     // Code shouldn't start with a store relative to $ra
@@ -405,20 +389,24 @@ fn is_invalid_start_instruction(
         .has_operand(rabbitizer::OperandType::cpu_immediate_base)
         && my_instruction.rs() == MipsGpr::ra
     {
-        // println!("store relative to $ra");
-        return true;
+        return Some(Rejection::RelativeToRa);
     }
 
     // Code shouldn't start with a reference to a register that isn't initialized
     if references_uninitialized(&my_instruction, &gpr_reg_states, &fpr_reg_states) {
-        // println!("references uninitialized");
-        return true;
+        return Some(Rejection::UninitializedRegister);
     }
 
-    false
+    None
 }
 
-pub fn count_invalid_start_instructions(region: &RomRegion, rom_bytes: &[u8]) -> usize {
+/// Number of instructions at the start of `region` that code is unlikely to start with, each of which is recorded in
+/// `trace`
+pub fn count_invalid_start_instructions(
+    region: &RomRegion,
+    rom_bytes: &[u8],
+    trace: &mut Trace,
+) -> usize {
     let mut gpr_reg_states: EnumMap<MipsGpr, RegisterState> = EnumMap::default();
     let mut fpr_reg_states: EnumMap<MipsFpr, RegisterState> = EnumMap::default();
 
@@ -456,14 +444,19 @@ pub fn count_invalid_start_instructions(region: &RomRegion, rom_bytes: &[u8]) ->
 
     let mut instr_index = 0;
     for chunk in rom_bytes[region.rom_start()..].chunks_exact(INSTRUCTION_SIZE) {
-        let my_instruction = MyInstruction(rabbitizer::Instruction::new(read_be_word(chunk), 0));
-
-        if !is_invalid_start_instruction(&my_instruction, &gpr_reg_states, &fpr_reg_states) {
-            break;
+        let word = read_be_word(chunk);
+        let my_instruction = MyInstruction(rabbitizer::Instruction::new(word, 0));
+
+        match start_rejection(&my_instruction, &gpr_reg_states, &fpr_reg_states) {
+            Some(rejection) => trace.record(TraceEvent::StartTrimmed(Rejected {
+                rom_addr: region.rom_start() + instr_index * INSTRUCTION_SIZE,
+                word,
+                rejection,
+            })),
+            None => break,
         }
         instr_index += 1;
     }
 
-    // println!("instr_index: {}", instr_index);
     return instr_index;
 }
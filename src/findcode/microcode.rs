@@ -4,6 +4,7 @@ use rabbitizer;
 // use strum_macros::EnumIter; // 0.17.1
 use super::{
     analysis::{MipsGpr, MyInstruction},
+    trace::{Rejected, Rejection},
     INSTRUCTION_SIZE,
 };
 use crate::utils::*;
@@ -44,34 +45,35 @@ pub fn instr_get_cop0_rd(my_instruction: &MyInstruction) -> Result<RSPCop0r, u32
     }
 }
 
-pub fn is_valid(my_instruction: &MyInstruction) -> bool {
+/// Check if a given instruction is valid RSP code, returning the rule that rejected it if it isn't
+pub fn rejection(my_instruction: &MyInstruction) -> Option<Rejection> {
     let id = my_instruction.0.instr_id();
 
     // Check for instructions with invalid opcodes
     if id == rabbitizer::InstrId::rsp_INVALID {
-        return false;
+        return Some(Rejection::InvalidRspInstruction);
     }
 
     // Check for instructions with invalid bits
     if !my_instruction.0.is_valid() {
         // ?
         // Make sure this isn't a special jr with
-        return false;
+        return Some(Rejection::InvalidRspInstruction);
     }
 
     // Check for arithmetic that outputs to $zero
     if my_instruction.0.modifies_rd() && my_instruction.rd() == MipsGpr::zero {
-        return false;
+        return Some(Rejection::WritesZero);
     }
     if my_instruction.0.modifies_rt() && my_instruction.rt() == MipsGpr::zero {
-        return false;
+        return Some(Rejection::WritesZero);
     }
 
     match id {
         // Check for mtc0 or mfc0 with invalid registers
         rabbitizer::InstrId::rsp_mtc0 | rabbitizer::InstrId::rsp_mfc0 => {
-            if instr_get_cop0_rd(&my_instruction).is_err() {
-                return false;
+            if let Err(reg) = instr_get_cop0_rd(&my_instruction) {
+                return Some(Rejection::InvalidCop0Register(reg));
             }
         }
 
@@ -80,10 +82,14 @@ pub fn is_valid(my_instruction: &MyInstruction) -> bool {
         | rabbitizer::InstrId::rsp_swc1
         | rabbitizer::InstrId::cpu_ctc0
         | rabbitizer::InstrId::cpu_cfc0
-        | rabbitizer::InstrId::rsp_cache => return false,
+        | rabbitizer::InstrId::rsp_cache => return Some(Rejection::NotOnRsp),
         _ => (),
     }
-    true
+    None
+}
+
+pub fn is_valid(my_instruction: &MyInstruction) -> bool {
+    rejection(my_instruction).is_none()
 }
 
 pub fn is_valid_bytes(bytes: &[u8]) -> bool {
@@ -91,11 +97,22 @@ pub fn is_valid_bytes(bytes: &[u8]) -> bool {
     is_valid(&my_instruction)
 }
 
-pub fn check_range(start: usize, end: usize, rom_bytes: &[u8]) -> bool {
+/// The RSP instruction at `rom_addr` and the rule that rejected it, if any
+pub fn rejected_at(rom_bytes: &[u8], rom_addr: usize) -> Option<Rejected> {
+    let word = read_be_word(&rom_bytes[rom_addr..]);
+    rejection(&MyInstruction::new_rsp(word)).map(|rejection| Rejected {
+        rom_addr,
+        word,
+        rejection,
+    })
+}
+
+/// Check if a given rom range is valid RSP instructions, returning the first one that isn't
+pub fn range_rejection(start: usize, end: usize, rom_bytes: &[u8]) -> Option<Rejected> {
     let mut prev_chunk = None;
     let mut identical_count = 0;
 
-    for chunk in rom_bytes[start..end].chunks_exact(INSTRUCTION_SIZE) {
+    for (i, chunk) in rom_bytes[start..end].chunks_exact(INSTRUCTION_SIZE).enumerate() {
         // Check if the previous instruction is identical to this one
         if Some(chunk) == prev_chunk {
             // If it is, increase the consecutive identical instruction count
@@ -106,14 +123,24 @@ pub fn check_range(start: usize, end: usize, rom_bytes: &[u8]) -> bool {
             identical_count = 0;
         }
 
-        let instr = MyInstruction::new_rsp(read_be_word(chunk));
-        // See check_range_cpu() for an explanation of this logic.
+        let rom_addr = start + i * INSTRUCTION_SIZE;
+        let word = read_be_word(chunk);
+        let instr = MyInstruction::new_rsp(word);
+        // See range_rejection() in findcode for an explanation of this logic.
         if (identical_count >= 3) && (instr.0.does_load() || instr.0.does_store()) {
-            return false;
+            return Some(Rejected {
+                rom_addr,
+                word,
+                rejection: Rejection::RepeatedLoadStore,
+            });
         }
-        if !is_valid_bytes(chunk) {
-            return false;
+        if let Some(rejection) = rejection(&instr) {
+            return Some(Rejected {
+                rom_addr,
+                word,
+                rejection,
+            });
         }
     }
-    true
+    None
 }
@@ -1,16 +1,17 @@
 pub mod analysis;
 pub mod microcode;
+pub mod trace;
 
 use std::fmt::Display;
+use std::ops::Range;
 
 use crate::utils::*;
-use crate::Args;
 use crate::INSTRUCTION_SIZE;
-use crate::IPL3_END;
 use analysis::MipsGpr;
 use analysis::MyInstruction;
+use trace::{Rejected, Rejection, Trace, TraceEvent};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct RomRegion {
     rom_start: usize,
     rom_end: usize,
@@ -69,17 +70,15 @@ fn is_unused_n64_instruction(id: rabbitizer::InstrId) -> bool {
     )
 }
 
-/// Check if a given instruction is valid via several metrics
-pub fn is_valid(my_instruction: &MyInstruction) -> bool {
+/// Check if a given instruction is valid via several metrics, returning the rule that rejected it if it isn't
+pub fn rejection(my_instruction: &MyInstruction) -> Option<Rejection> {
     let id = my_instruction.0.instr_id();
 
     // Check for instructions with invalid bits or invalid opcodes
     if !rabbitizer::Instruction::is_valid(&my_instruction.0)
         || id == rabbitizer::InstrId::cpu_INVALID
     {
-        // println!("Invalid instruction: {:08X}", my_instruction.0.raw());
-        // println!("    {:08X} ({})", my_instruction.0.raw(), my_instruction.0.disassemble(None, 0));
-        return false;
+        return Some(Rejection::InvalidInstruction);
     }
 
     let is_store = my_instruction.0.does_store();
@@ -87,8 +86,7 @@ pub fn is_valid(my_instruction: &MyInstruction) -> bool {
 
     // Check for loads or stores with an offset from $zero
     if (is_store || is_load) && (my_instruction.rs() == MipsGpr::zero) {
-        // println!("Loads or stores with an offset from $zero");
-        return false;
+        return Some(Rejection::LoadStoreFromZero);
     }
 
     // This check is disabled as some compilers can generate load to $zero for a volatile dereference
@@ -100,30 +98,25 @@ pub fn is_valid(my_instruction: &MyInstruction) -> bool {
 
     // Check for arithmetic that outputs to $zero
     if my_instruction.0.modifies_rd() && my_instruction.rd() == MipsGpr::zero {
-        return false;
+        return Some(Rejection::WritesZero);
     }
     if my_instruction.0.modifies_rt() && my_instruction.rt() == MipsGpr::zero {
-        return false;
+        return Some(Rejection::WritesZero);
     }
 
     // Check for mtc0 or mfc0 with invalid registers
     if matches!(
         id,
         rabbitizer::InstrId::cpu_mtc0 | rabbitizer::InstrId::cpu_mfc0
-    ) && my_instruction.instr_get_cop0_rd().is_err()
-    {
-        // println!(
-        //     "mtc0 or mfc0 with invalid registers: {} ({:08X})",
-        //     my_instruction.instr_get_cop0_rd().unwrap_err(),
-        //     my_instruction.0.raw()
-        // );
-        return false;
+    ) {
+        if let Err(reg) = my_instruction.instr_get_cop0_rd() {
+            return Some(Rejection::InvalidCop0Register(reg));
+        }
     }
 
     // Check for instructions that wouldn't be in an N64 game, despite being valid
     if is_unused_n64_instruction(id) {
-        // println!("Valid but not in N64");
-        return false;
+        return Some(Rejection::UnusedOnN64);
     }
 
     // Check for cache instructions with invalid parameters
@@ -134,8 +127,7 @@ pub fn is_valid(my_instruction: &MyInstruction) -> bool {
 
         // Only cache operations 0-6 and cache types 0-1 are valid
         if cache_op > 6 || cache_type > 1 {
-            // println!("Cache instructions with invalid parameters");
-            return false;
+            return Some(Rejection::InvalidCacheOp);
         }
     }
 
@@ -147,14 +139,12 @@ pub fn is_valid(my_instruction: &MyInstruction) -> bool {
             | rabbitizer::InstrId::cpu_swc2
             | rabbitizer::InstrId::cpu_sdc2
     ) {
-        // println!("cop2");
-        return false;
+        return Some(Rejection::Cop2);
     }
 
     // Check for trap instructions
     if my_instruction.0.is_trap() {
-        // println!("trap");
-        return false;
+        return Some(Rejection::Trap);
     }
 
     // Check for ctc0 and cfc0, which aren't valid on the N64
@@ -162,17 +152,20 @@ pub fn is_valid(my_instruction: &MyInstruction) -> bool {
         id,
         rabbitizer::InstrId::cpu_ctc0 | rabbitizer::InstrId::cpu_cfc0
     ) {
-        // println!("ctc0 or cfc0");
-        return false;
+        return Some(Rejection::Ctc0OrCfc0);
     }
 
     // Check for instructions that don't exist on the N64's CPU
     if matches!(id, rabbitizer::InstrId::cpu_pref) {
-        // println!("does not exist on the N64's CPU");
-        return false;
+        return Some(Rejection::NotOnN64Cpu);
     }
 
-    true
+    None
+}
+
+/// Check if a given instruction is valid via several metrics
+pub fn is_valid(my_instruction: &MyInstruction) -> bool {
+    rejection(my_instruction).is_none()
 }
 
 fn is_valid_bytes(bytes: &[u8]) -> bool {
@@ -180,10 +173,20 @@ fn is_valid_bytes(bytes: &[u8]) -> bool {
     is_valid(&my_instruction)
 }
 
+/// The CPU instruction at `rom_addr` and the rule that rejected it, if any
+fn rejected_at(rom_bytes: &[u8], rom_addr: usize) -> Option<Rejected> {
+    let word = read_be_word(&rom_bytes[rom_addr..]);
+    rejection(&MyInstruction::new(word)).map(|rejection| Rejected {
+        rom_addr,
+        word,
+        rejection,
+    })
+}
+
 const JR_RA: u32 = 0x03E00008;
 
 /// Search a span for any instances of the instruction `jr $ra`
-fn find_return_locations(rom_bytes: &[u8]) -> Vec<usize> {
+fn find_return_locations(rom_bytes: &[u8], search_start: usize, trace: &mut Trace) -> Vec<usize> {
     // let locations = rom_bytes[IPL3_END..]
     //     .chunks_exact(INSTRUCTION_SIZE)
     //     .enumerate()
@@ -198,22 +201,21 @@ fn find_return_locations(rom_bytes: &[u8]) -> Vec<usize> {
     //     .collect::<Vec<_>>();
 
     let mut filtered_locations = Vec::new();
-    let mut iter = rom_bytes[IPL3_END..]
+    let mut iter = rom_bytes[search_start..]
         .chunks_exact(INSTRUCTION_SIZE)
         .enumerate();
     while let Some((i, chunk)) = iter.next() {
         if read_be_word(chunk) == JR_RA {
             if let Some((_, chunk)) = iter.next() {
+                let rom_addr = INSTRUCTION_SIZE * i + search_start;
                 if is_valid_bytes(chunk) || microcode::is_valid_bytes(chunk) {
-                    filtered_locations.push(INSTRUCTION_SIZE * i + IPL3_END);
-                    // } else {
-                    //     println!(
-                    //         "{:8X}: {}",
-                    //         INSTRUCTION_SIZE * i + IPL3_END,
-                    //         MyInstruction::new(read_be_word(chunk))
-                    //             .0
-                    //             .disassemble(None, 0)
-                    //     )
+                    filtered_locations.push(rom_addr);
+                    trace.record(TraceEvent::ReturnFound { rom_addr });
+                } else if let Some(delay_slot) = rejected_at(rom_bytes, rom_addr + INSTRUCTION_SIZE) {
+                    trace.record(TraceEvent::ReturnRejected {
+                        rom_addr,
+                        delay_slot,
+                    });
                 }
             }
         }
@@ -233,8 +235,13 @@ fn find_return_locations(rom_bytes: &[u8]) -> Vec<usize> {
     filtered_locations
 }
 
-/// Searches backwards from the given rom address until it hits an invalid instruction
-fn find_code_start(rom_bytes: &[u8], rom_addr: usize) -> usize {
+/// Searches backwards from the given rom address until it hits an invalid instruction or `search_start`
+fn find_code_start(
+    rom_bytes: &[u8],
+    rom_addr: usize,
+    search_start: usize,
+    trace: &mut Trace,
+) -> usize {
     // IPL3_END
     //     + INSTRUCTION_SIZE
     //         * rom_bytes[IPL3_END..rom_addr]
@@ -242,20 +249,25 @@ fn find_code_start(rom_bytes: &[u8], rom_addr: usize) -> usize {
     //             .rposition(|v| !is_valid_bytes(v))
     //             .unwrap_or(0)
     let mut r = rom_addr;
-    // println!("start initial {r:6X}");
-    while r > IPL3_END {
+    let mut stopped_by = None;
+    while r > search_start {
         let cr = r - INSTRUCTION_SIZE;
-        if !is_valid_bytes(&rom_bytes[cr..]) {
+        stopped_by = rejected_at(rom_bytes, cr);
+        if stopped_by.is_some() {
             break;
         }
         r = cr;
     }
-    // println!("start {r:6X}");
+    trace.record(TraceEvent::RegionStart {
+        rom_addr: r,
+        from: rom_addr,
+        stopped_by,
+    });
     return r;
 }
 
-/// Searches forwards from the given rom address until it hits an invalid instruction
-fn find_code_end(rom_bytes: &[u8], rom_addr: usize) -> usize {
+/// Searches forwards from the given rom address until it hits an invalid instruction or the end of `rom_bytes`
+fn find_code_end(rom_bytes: &[u8], rom_addr: usize, trace: &mut Trace) -> usize {
     // rom_addr
     //     + INSTRUCTION_SIZE
     //         * rom_bytes[rom_addr..]
@@ -264,14 +276,19 @@ fn find_code_end(rom_bytes: &[u8], rom_addr: usize) -> usize {
     //             .unwrap_or(rom_bytes.len())
 
     let mut r = rom_addr;
-    // println!("end initial {r:6X}");
-    while r > 0 {
-        if !is_valid_bytes(&rom_bytes[r..]) {
+    let mut stopped_by = None;
+    while r + INSTRUCTION_SIZE <= rom_bytes.len() {
+        stopped_by = rejected_at(rom_bytes, r);
+        if stopped_by.is_some() {
             break;
         }
         r += INSTRUCTION_SIZE;
     }
-    // println!("end {r:6X}");
+    trace.record(TraceEvent::RegionEnd {
+        rom_addr: r,
+        from: rom_addr,
+        stopped_by,
+    });
     return r;
 }
 
@@ -286,37 +303,54 @@ fn is_unconditional_branch(bytes: &[u8]) -> bool {
 }
 
 /// Trims zeroes from the start of a code region and "loose" instructions from the end
-fn trim_region(codeseg: &mut RomRegion, rom_bytes: &[u8]) {
+fn trim_region(codeseg: &mut RomRegion, rom_bytes: &[u8], trace: &mut Trace) {
     let mut start = codeseg.rom_start();
     let mut end = codeseg.rom_end();
-    let invalid_start_count = analysis::count_invalid_start_instructions(codeseg, rom_bytes);
+    let invalid_start_count =
+        analysis::count_invalid_start_instructions(codeseg, rom_bytes, trace);
 
     start += invalid_start_count * INSTRUCTION_SIZE;
 
     // Remove leading nops
+    let nops_start = start;
     start += INSTRUCTION_SIZE
         * &rom_bytes[start..]
             .chunks_exact(INSTRUCTION_SIZE)
             .position(|v| read_be_word(v) != 0)
             .unwrap_or(0);
+    if start != nops_start {
+        trace.record(TraceEvent::NopsTrimmed {
+            from: nops_start,
+            to: start,
+        });
+    }
 
     // Any instruction that isn't eventually followed by an unconditional non-linking branch (b, j, jr) would run into
     // invalid code, so scan backwards until we see an unconditional branch and remove anything after it.
     // Scan two instructions back (8 bytes before the end) instead of one to include the delay slot.
-    while !is_unconditional_branch(&rom_bytes[end - 2 * INSTRUCTION_SIZE..]) && end > start {
+    while end > start
+        && end >= 2 * INSTRUCTION_SIZE
+        && !is_unconditional_branch(&rom_bytes[end - 2 * INSTRUCTION_SIZE..])
+    {
         end -= INSTRUCTION_SIZE;
     }
+    if end != codeseg.rom_end() {
+        trace.record(TraceEvent::EndTrimmed {
+            from: codeseg.rom_end(),
+            to: end,
+        });
+    }
 
     codeseg.set_rom_start(start);
     codeseg.set_rom_end(end);
 }
 
-/// Check if a given rom range is valid CPU instructions
-fn check_range(start: usize, end: usize, rom_bytes: &[u8]) -> bool {
+/// Check if a given rom range is valid CPU instructions, returning the first one that isn't
+fn range_rejection(start: usize, end: usize, rom_bytes: &[u8]) -> Option<Rejected> {
     let mut prev_chunk = None;
     let mut identical_count = 0;
 
-    for chunk in rom_bytes[start..end].chunks_exact(INSTRUCTION_SIZE) {
+    for (i, chunk) in rom_bytes[start..end].chunks_exact(INSTRUCTION_SIZE).enumerate() {
         // Check if the previous instruction is identical to this one
         if Some(chunk) == prev_chunk {
             // If it is, increase the consecutive identical instruction count
@@ -327,29 +361,108 @@ fn check_range(start: usize, end: usize, rom_bytes: &[u8]) -> bool {
             identical_count = 0;
         }
 
-        let instr = MyInstruction::new(read_be_word(chunk));
+        let rom_addr = start + i * INSTRUCTION_SIZE;
+        let word = read_be_word(chunk);
+        let instr = MyInstruction::new(word);
         // If there are 3 identical loads or stores in a row, it's not likely to be real code
         // Use 3 as the count because 2 could be plausible if it's a duplicated instruction by the compiler.
         // Only check for loads and stores because arithmetic could be duplicated to avoid more expensive operations,
         // e.g. x + x + x instead of 3 * x.
         if (identical_count >= 3) && (instr.0.does_load() || instr.0.does_store()) {
-            return false;
+            return Some(Rejected {
+                rom_addr,
+                word,
+                rejection: Rejection::RepeatedLoadStore,
+            });
         }
-        if !is_valid(&instr) {
-            return false;
+        if let Some(rejection) = rejection(&instr) {
+            return Some(Rejected {
+                rom_addr,
+                word,
+                rejection,
+            });
         }
     }
-    true
+    None
 }
 
-pub fn find_code_regions(args: &Args, rom_bytes: &[u8]) -> Vec<RomRegion> {
-    let mut regions = Vec::with_capacity(0x400);
-    let return_addrs = find_return_locations(rom_bytes);
+/// Number of `jr $ra` instructions found outside the ranges in `excluded`, which are skipped as they are by
+/// `find_code_regions_excluding`. This is a crude estimate of the number of functions; it will overestimate if an early
+/// return is present.
+pub fn estimate_function_count(rom_bytes: &[u8], search_start: usize, excluded: &[Range<usize>]) -> usize {
+    let zones = exclusion_zones(rom_bytes.len(), search_start, excluded);
+    spans_between(rom_bytes.len(), search_start, &zones)
+        .map(|span| find_return_locations(&rom_bytes[..span.end], span.start, &mut Trace::disabled()).len())
+        .sum()
+}
+
+/// Find code regions at or after `search_start`, which is normally `IPL3_END`. To search a window of a rom, pass a slice
+/// that ends at the end of the window so that addresses stay relative to the start of the rom.
+pub fn find_code_regions(rom_bytes: &[u8], search_start: usize) -> Vec<RomRegion> {
+    find_code_regions_traced(rom_bytes, search_start, &mut Trace::disabled())
+}
+
+/// `find_code_regions_traced`, skipping every range in `excluded`, e.g. compressed segments or data blocks known from
+/// elsewhere. Each span between them is searched separately, so no region starts in, runs into or is merged across
+/// an excluded range.
+pub fn find_code_regions_excluding(
+    rom_bytes: &[u8],
+    search_start: usize,
+    excluded: &[Range<usize>],
+    trace: &mut Trace,
+) -> Vec<RomRegion> {
+    let zones = exclusion_zones(rom_bytes.len(), search_start, excluded);
+    for zone in &zones {
+        trace.record(TraceEvent::Excluded(zone.clone()));
+    }
 
-    if args.estimate_function_count {
-        println!();
-        println!("Estimated function count: {}", return_addrs.len());
+    let mut regions = Vec::new();
+    for span in spans_between(rom_bytes.len(), search_start, &zones) {
+        regions.append(&mut find_code_regions_traced(&rom_bytes[..span.end], span.start, trace));
     }
+    regions
+}
+
+/// `excluded`, widened to whole instructions, clamped to the search, sorted, and merged where they overlap
+fn exclusion_zones(rom_len: usize, search_start: usize, excluded: &[Range<usize>]) -> Vec<Range<usize>> {
+    let mut zones = excluded
+        .iter()
+        .map(|range| {
+            round_down(range.start, INSTRUCTION_SIZE).max(search_start)
+                ..round_up(range.end, INSTRUCTION_SIZE).min(rom_len)
+        })
+        .filter(|range| !range.is_empty())
+        .collect::<Vec<_>>();
+    zones.sort_by_key(|range| range.start);
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(zones.len());
+    for zone in zones {
+        match merged.last_mut() {
+            Some(last) if zone.start <= last.end => last.end = last.end.max(zone.end),
+            _ => merged.push(zone),
+        }
+    }
+    merged
+}
+
+/// The non-empty spans from `search_start` to `rom_len` around `zones`, which must be as `exclusion_zones` returns them
+fn spans_between(
+    rom_len: usize,
+    search_start: usize,
+    zones: &[Range<usize>],
+) -> impl Iterator<Item = Range<usize>> + '_ {
+    let starts = std::iter::once(search_start).chain(zones.iter().map(|zone| zone.end));
+    let ends = zones.iter().map(|zone| zone.start).chain(std::iter::once(rom_len));
+    starts.zip(ends).map(|(start, end)| start..end).filter(|span| !span.is_empty())
+}
+
+/// `find_code_regions`, recording in `trace` why each region starts and ends where it does
+pub fn find_code_regions_traced(
+    rom_bytes: &[u8],
+    search_start: usize,
+    trace: &mut Trace,
+) -> Vec<RomRegion> {
+    let mut regions = Vec::with_capacity(0x400);
+    let return_addrs = find_return_locations(rom_bytes, search_start, trace);
 
     // let mut it = return_addrs.iter();
     // let mut i = 0;
@@ -358,8 +471,8 @@ pub fn find_code_regions(args: &Args, rom_bytes: &[u8]) -> Vec<RomRegion> {
     'outer: while let Some(mut cur) = iter.next() {
         // println!("");
         // println!("index: {i}, it: {cur:X}");
-        let region_start = find_code_start(rom_bytes, *cur);
-        let region_end = find_code_end(rom_bytes, *cur);
+        let region_start = find_code_start(rom_bytes, *cur, search_start, trace);
+        let region_end = find_code_end(rom_bytes, *cur, trace);
         regions.push(RomRegion::new(region_start, region_end));
 
         // println!("{:?}", regions);
@@ -378,7 +491,7 @@ pub fn find_code_regions(args: &Args, rom_bytes: &[u8]) -> Vec<RomRegion> {
         //     println!("{}", region);
         // }
         // println!("Trim");
-        trim_region(regions.last_mut().unwrap(), rom_bytes);
+        trim_region(regions.last_mut().unwrap(), rom_bytes, trace);
         // for region in &regions {
         //     println!("{}", region);
         // }
@@ -391,18 +504,27 @@ pub fn find_code_regions(args: &Args, rom_bytes: &[u8]) -> Vec<RomRegion> {
             // println!("{last_start:X}, {:X}", penultimate.rom_end());
             if last_start - penultimate.rom_end() < microcode::CHECK_THRESHHOLD {
                 // println!("Check for ucode");
+                let gap = penultimate.rom_end()..last_start;
                 // Check if there's a range of valid CPU instructions between these two regions
-                let mut valid_range = check_range(penultimate.rom_end(), last_start, rom_bytes);
-
-                // If there isn't check for RSP instructions
-                if !valid_range {
-                    valid_range =
-                        microcode::check_range(penultimate.rom_end(), last_start, rom_bytes);
-                    // If RSP instructions were found, mark the first region as having RSP instructions
-                    if valid_range {
-                        penultimate.set_has_rsp(true);
+                let valid_range = match range_rejection(gap.start, gap.end, rom_bytes) {
+                    None => {
+                        trace.record(TraceEvent::Merged { gap, rsp: false });
+                        true
                     }
-                }
+                    // If there isn't check for RSP instructions
+                    Some(cpu) => match microcode::range_rejection(gap.start, gap.end, rom_bytes) {
+                        None => {
+                            // If RSP instructions were found, mark the first region as having RSP instructions
+                            penultimate.set_has_rsp(true);
+                            trace.record(TraceEvent::Merged { gap, rsp: true });
+                            true
+                        }
+                        Some(rsp) => {
+                            trace.record(TraceEvent::NotMerged { gap, cpu, rsp });
+                            false
+                        }
+                    },
+                };
                 if valid_range {
                     let new_end = regions.last().unwrap().rom_end();
                     regions.pop();
@@ -416,7 +538,8 @@ pub fn find_code_regions(args: &Args, rom_bytes: &[u8]) -> Vec<RomRegion> {
             // println!("Has rsp.");
             // Keep advancing the region's end until either the stop point is reached or something
             // that isn't a valid RSP instruction is seen
-            let mut cur_end = regions.last().unwrap().rom_end();
+            let rsp_start = regions.last().unwrap().rom_end();
+            let mut cur_end = rsp_start;
             while regions.last().unwrap().rom_end() < rom_bytes.len()
                 && microcode::is_valid_bytes(&rom_bytes[cur_end..])
             {
@@ -427,9 +550,18 @@ pub fn find_code_regions(args: &Args, rom_bytes: &[u8]) -> Vec<RomRegion> {
                     .set_rom_end(cur_end + INSTRUCTION_SIZE);
                 cur_end = regions.last().unwrap().rom_end();
             }
+            if trace.is_enabled() {
+                trace.record(TraceEvent::RspExtended {
+                    from: rsp_start,
+                    to: cur_end,
+                    stopped_by: (cur_end + INSTRUCTION_SIZE <= rom_bytes.len())
+                        .then(|| microcode::rejected_at(rom_bytes, cur_end))
+                        .flatten(),
+                });
+            }
 
             // Trim the region again to get rid of any junk that may have been found after its end
-            trim_region(regions.last_mut().unwrap(), rom_bytes);
+            trim_region(regions.last_mut().unwrap(), rom_bytes, trace);
 
             // Skip any return addresses that are now part of the region
             while cur < &regions.last().unwrap().rom_end() {
@@ -440,7 +572,11 @@ pub fn find_code_regions(args: &Args, rom_bytes: &[u8]) -> Vec<RomRegion> {
             }
         }
     }
-    // println!("{:?}", regions);
+    if trace.is_enabled() {
+        for region in &regions {
+            trace.record(TraceEvent::Region(region.clone()));
+        }
+    }
 
     regions
 }
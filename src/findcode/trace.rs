@@ -0,0 +1,342 @@
+use std::fmt::Display;
+use std::ops::Range;
+
+use super::RomRegion;
+use crate::INSTRUCTION_SIZE;
+
+/// The rule that rejected an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    InvalidInstruction,
+    LoadStoreFromZero,
+    WritesZero,
+    InvalidCop0Register(u32),
+    UnusedOnN64,
+    InvalidCacheOp,
+    Cop2,
+    Trap,
+    Ctc0OrCfc0,
+    NotOnN64Cpu,
+    // Only at the start of a region
+    StartsWithNop,
+    JumpToZero,
+    ShiftOfZero,
+    StartsWithMthiOrMtlo,
+    Cop1ConditionBranch,
+    TrappingArithmetic,
+    UnconditionalBranch,
+    Links,
+    RelativeToRa,
+    UninitializedRegister,
+    // Only in a range between regions
+    RepeatedLoadStore,
+    // RSP
+    InvalidRspInstruction,
+    NotOnRsp,
+}
+
+impl Rejection {
+    /// Stable identifier for the rule, for machine-readable output
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rejection::InvalidInstruction => "invalid_instruction",
+            Rejection::LoadStoreFromZero => "load_store_from_zero",
+            Rejection::WritesZero => "writes_zero",
+            Rejection::InvalidCop0Register(_) => "invalid_cop0_register",
+            Rejection::UnusedOnN64 => "unused_on_n64",
+            Rejection::InvalidCacheOp => "invalid_cache_op",
+            Rejection::Cop2 => "cop2",
+            Rejection::Trap => "trap",
+            Rejection::Ctc0OrCfc0 => "ctc0_or_cfc0",
+            Rejection::NotOnN64Cpu => "not_on_n64_cpu",
+            Rejection::StartsWithNop => "starts_with_nop",
+            Rejection::JumpToZero => "jump_to_zero",
+            Rejection::ShiftOfZero => "shift_of_zero",
+            Rejection::StartsWithMthiOrMtlo => "starts_with_mthi_or_mtlo",
+            Rejection::Cop1ConditionBranch => "cop1_condition_branch",
+            Rejection::TrappingArithmetic => "trapping_arithmetic",
+            Rejection::UnconditionalBranch => "unconditional_branch",
+            Rejection::Links => "links",
+            Rejection::RelativeToRa => "relative_to_ra",
+            Rejection::UninitializedRegister => "uninitialized_register",
+            Rejection::RepeatedLoadStore => "repeated_load_store",
+            Rejection::InvalidRspInstruction => "invalid_rsp_instruction",
+            Rejection::NotOnRsp => "not_on_rsp",
+        }
+    }
+}
+
+impl Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rejection::InvalidInstruction => write!(f, "invalid instruction"),
+            Rejection::LoadStoreFromZero => write!(f, "load or store with an offset from $zero"),
+            Rejection::WritesZero => write!(f, "arithmetic that outputs to $zero"),
+            Rejection::InvalidCop0Register(reg) => {
+                write!(f, "mtc0 or mfc0 with invalid register {}", reg)
+            }
+            Rejection::UnusedOnN64 => write!(f, "valid, but not used in N64 games"),
+            Rejection::InvalidCacheOp => write!(f, "cache instruction with invalid parameters"),
+            Rejection::Cop2 => write!(f, "cop2 instruction, which the N64's CPU doesn't have"),
+            Rejection::Trap => write!(f, "trap instruction"),
+            Rejection::Ctc0OrCfc0 => write!(f, "ctc0 or cfc0, which aren't valid on the N64"),
+            Rejection::NotOnN64Cpu => write!(f, "does not exist on the N64's CPU"),
+            Rejection::StartsWithNop => write!(f, "code probably won't start with a nop"),
+            Rejection::JumpToZero => write!(f, "jump to $zero"),
+            Rejection::ShiftOfZero => write!(f, "shift with $zero as input and non-zero sa"),
+            Rejection::StartsWithMthiOrMtlo => write!(f, "code probably won't start with mthi or mtlo"),
+            Rejection::Cop1ConditionBranch => {
+                write!(f, "branch on the cop1 condition flag, which won't have been set yet")
+            }
+            Rejection::TrappingArithmetic => {
+                write!(f, "add/sub/addi, where addu/subu/addiu would normally be used")
+            }
+            Rejection::UnconditionalBranch => write!(f, "code shouldn't start with an unconditional branch"),
+            Rejection::Links => write!(f, "linked jump before the return address could be saved"),
+            Rejection::RelativeToRa => write!(f, "load or store relative to $ra"),
+            Rejection::UninitializedRegister => write!(f, "references an uninitialized register"),
+            Rejection::RepeatedLoadStore => write!(f, "the same load or store 3 or more times in a row"),
+            Rejection::InvalidRspInstruction => write!(f, "invalid RSP instruction"),
+            Rejection::NotOnRsp => write!(f, "does not exist on the RSP"),
+        }
+    }
+}
+
+/// An instruction that a rule fired on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rejected {
+    pub rom_addr: usize,
+    pub word: u32,
+    pub rejection: Rejection,
+}
+
+impl Display for Rejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:08X} {:08X} ({}): {}",
+            self.rom_addr,
+            self.word,
+            rabbitizer::Instruction::new(self.word, 0).disassemble(None, 0),
+            self.rejection
+        )
+    }
+}
+
+/// A decision made while finding code regions
+#[derive(Debug, Clone)]
+pub enum TraceEvent {
+    /// A `jr $ra` whose delay slot is valid, so a region is searched for around it
+    ReturnFound { rom_addr: usize },
+    /// A `jr $ra` whose delay slot is not valid CPU or RSP code
+    ReturnRejected { rom_addr: usize, delay_slot: Rejected },
+    /// Where the backwards search from the `jr $ra` at `from` stopped, and the instruction that stopped it. `None` if
+    /// it reached the start of the search.
+    RegionStart {
+        rom_addr: usize,
+        from: usize,
+        stopped_by: Option<Rejected>,
+    },
+    /// Where the forwards search from the `jr $ra` at `from` stopped, and the instruction that stopped it. `None` if it
+    /// reached the end of the rom.
+    RegionEnd {
+        rom_addr: usize,
+        from: usize,
+        stopped_by: Option<Rejected>,
+    },
+    /// An instruction trimmed from the start of a region because code is unlikely to start with it
+    StartTrimmed(Rejected),
+    /// Nops trimmed from the start of a region
+    NopsTrimmed { from: usize, to: usize },
+    /// Instructions after the last unconditional branch trimmed from the end of a region
+    EndTrimmed { from: usize, to: usize },
+    /// Two regions merged because the gap between them is valid code
+    Merged { gap: Range<usize>, rsp: bool },
+    /// Two nearby regions not merged, because of an instruction in the gap that is valid as neither CPU nor RSP code
+    NotMerged {
+        gap: Range<usize>,
+        cpu: Rejected,
+        rsp: Rejected,
+    },
+    /// A region with microcode extended to the end of the valid RSP instructions after it
+    RspExtended {
+        from: usize,
+        to: usize,
+        stopped_by: Option<Rejected>,
+    },
+    /// The final extent of a region
+    Region(RomRegion),
+    /// A range skipped by the search, because it is compressed or was excluded by the user
+    Excluded(Range<usize>),
+}
+
+impl TraceEvent {
+    /// Stable identifier for the kind of event, for machine-readable output
+    pub fn name(&self) -> &'static str {
+        match self {
+            TraceEvent::ReturnFound { .. } => "return_found",
+            TraceEvent::ReturnRejected { .. } => "return_rejected",
+            TraceEvent::RegionStart { .. } => "region_start",
+            TraceEvent::RegionEnd { .. } => "region_end",
+            TraceEvent::StartTrimmed(_) => "start_trimmed",
+            TraceEvent::NopsTrimmed { .. } => "nops_trimmed",
+            TraceEvent::EndTrimmed { .. } => "end_trimmed",
+            TraceEvent::Merged { .. } => "merged",
+            TraceEvent::NotMerged { .. } => "not_merged",
+            TraceEvent::RspExtended { .. } => "rsp_extended",
+            TraceEvent::Region(_) => "region",
+            TraceEvent::Excluded(_) => "excluded",
+        }
+    }
+
+    /// The instructions whose rejection caused the event
+    pub fn rejected(&self) -> Vec<&Rejected> {
+        match self {
+            TraceEvent::ReturnRejected { delay_slot, .. } => vec![delay_slot],
+            TraceEvent::RegionStart { stopped_by, .. }
+            | TraceEvent::RegionEnd { stopped_by, .. }
+            | TraceEvent::RspExtended { stopped_by, .. } => stopped_by.iter().collect(),
+            TraceEvent::StartTrimmed(rejected) => vec![rejected],
+            TraceEvent::NotMerged { cpu, rsp, .. } => vec![cpu, rsp],
+            _ => Vec::new(),
+        }
+    }
+
+    /// The range of rom addresses the event is about, including the instructions at either end
+    pub fn span(&self) -> Range<usize> {
+        let between = |a: usize, b: usize| a.min(b)..a.max(b) + INSTRUCTION_SIZE;
+
+        match self {
+            TraceEvent::ReturnFound { rom_addr } | TraceEvent::ReturnRejected { rom_addr, .. } => {
+                between(*rom_addr, *rom_addr)
+            }
+            TraceEvent::StartTrimmed(rejected) => between(rejected.rom_addr, rejected.rom_addr),
+            TraceEvent::RegionStart {
+                rom_addr: to, from, ..
+            }
+            | TraceEvent::RegionEnd {
+                rom_addr: to, from, ..
+            }
+            | TraceEvent::NopsTrimmed { from, to }
+            | TraceEvent::EndTrimmed { from, to }
+            | TraceEvent::RspExtended { from, to, .. } => between(*from, *to),
+            TraceEvent::Merged { gap, .. } | TraceEvent::NotMerged { gap, .. } => {
+                between(gap.start, gap.end)
+            }
+            TraceEvent::Region(region) => region.rom_start()..region.rom_end(),
+            TraceEvent::Excluded(range) => range.clone(),
+        }
+    }
+}
+
+impl Display for TraceEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TraceEvent::ReturnFound { rom_addr } => write!(f, "{:08X}: jr $ra", rom_addr),
+            TraceEvent::ReturnRejected {
+                rom_addr,
+                delay_slot,
+            } => write!(
+                f,
+                "{:08X}: jr $ra ignored, invalid delay slot {}",
+                rom_addr, delay_slot
+            ),
+            TraceEvent::RegionStart {
+                rom_addr,
+                from,
+                stopped_by,
+            } => {
+                write!(f, "{:08X}: region start, searching back from {:08X}", rom_addr, from)?;
+                match stopped_by {
+                    Some(rejected) => write!(f, ", stopped after {}", rejected),
+                    None => write!(f, ", reached the start of the search"),
+                }
+            }
+            TraceEvent::RegionEnd {
+                rom_addr,
+                from,
+                stopped_by,
+            } => {
+                write!(f, "{:08X}: region end, searching forward from {:08X}", rom_addr, from)?;
+                match stopped_by {
+                    Some(rejected) => write!(f, ", stopped at {}", rejected),
+                    None => write!(f, ", reached the end of the rom"),
+                }
+            }
+            TraceEvent::StartTrimmed(rejected) => write!(f, "start trimmed: {}", rejected),
+            TraceEvent::NopsTrimmed { from, to } => {
+                write!(f, "{:08X}: leading nops trimmed, start moved to {:08X}", from, to)
+            }
+            TraceEvent::EndTrimmed { from, to } => write!(
+                f,
+                "{:08X}: not followed by an unconditional branch, end moved back to {:08X}",
+                from, to
+            ),
+            TraceEvent::Merged { gap, rsp } => write!(
+                f,
+                "[{:08X}, {:08X}): gap is valid {} code, regions merged",
+                gap.start,
+                gap.end,
+                if *rsp { "RSP" } else { "CPU" }
+            ),
+            TraceEvent::NotMerged { gap, cpu, rsp } => write!(
+                f,
+                "[{:08X}, {:08X}): regions not merged, CPU: {}; RSP: {}",
+                gap.start, gap.end, cpu, rsp
+            ),
+            TraceEvent::RspExtended {
+                from,
+                to,
+                stopped_by,
+            } => {
+                write!(f, "{:08X}: RSP region extended to {:08X}", from, to)?;
+                match stopped_by {
+                    Some(rejected) => write!(f, ", stopped at {}", rejected),
+                    None => write!(f, ", reached the end of the rom"),
+                }
+            }
+            TraceEvent::Region(region) => write!(f, "region {}", region),
+            TraceEvent::Excluded(range) => write!(
+                f,
+                "[{:08X}, {:08X}): excluded from the search",
+                range.start, range.end
+            ),
+        }
+    }
+}
+
+/// Collects the events that touch a range of rom addresses. An empty range collects nothing.
+#[derive(Debug, Default)]
+pub struct Trace {
+    range: Range<usize>,
+    events: Vec<TraceEvent>,
+}
+
+impl Trace {
+    pub fn new(range: Range<usize>) -> Self {
+        Self {
+            range,
+            events: Vec::new(),
+        }
+    }
+
+    /// A trace that records nothing
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.range.is_empty()
+    }
+
+    pub fn record(&mut self, event: TraceEvent) {
+        let span = event.span();
+        if span.start < self.range.end && self.range.start < span.end {
+            self.events.push(event);
+        }
+    }
+
+    pub fn into_events(self) -> Vec<TraceEvent> {
+        self.events
+    }
+}
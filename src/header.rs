@@ -0,0 +1,229 @@
+use std::fmt::Display;
+
+use crate::error::{AnalyserError, Result};
+use crate::utils::*;
+
+pub const HEADER_SIZE: usize = 0x40;
+
+const KSEG0_START: u32 = 0x80000000;
+const KSEG1_START: u32 = 0xA0000000;
+
+/// Standard value of the first header word, `0x80371240`
+const STANDARD_PI_BSD_DOM1_CONFIG: PiBsdDomainConfig = PiBsdDomainConfig {
+    endian: 0x80,
+    release: 0x3,
+    page_size: 0x7,
+    pulse_width: 0x12,
+    latency: 0x40,
+};
+
+/// PI timings for cartridge domain 1, which the IPL2 sets up before loading the IPL3
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PiBsdDomainConfig {
+    pub endian: u8,
+    pub release: u8,
+    pub page_size: u8,
+    pub pulse_width: u8,
+    pub latency: u8,
+}
+
+impl PiBsdDomainConfig {
+    fn from_word(word: u32) -> Self {
+        let [endian, rls_pgs, pulse_width, latency] = word.to_be_bytes();
+        Self {
+            endian,
+            release: rls_pgs >> 4,
+            page_size: rls_pgs & 0xF,
+            pulse_width,
+            latency,
+        }
+    }
+
+    pub fn to_word(self) -> u32 {
+        u32::from_be_bytes([
+            self.endian,
+            (self.release << 4) | self.page_size,
+            self.pulse_width,
+            self.latency,
+        ])
+    }
+}
+
+impl Display for PiBsdDomainConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "LAT: 0x{:02X}, PWD: 0x{:02X}, PGS: 0x{:X}, RLS: 0x{:X}",
+            self.latency, self.pulse_width, self.page_size, self.release
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RomHeader {
+    pub pi_bsd_dom1_config: PiBsdDomainConfig,
+    pub clock_rate: u32,
+    /// Boot address as written in the header; some CICs adjust this, see `ipl3`
+    pub entrypoint: u32,
+    pub libultra_release: u32,
+    pub crc1: u32,
+    pub crc2: u32,
+    pub name: String,
+    pub category_code: u8,
+    pub game_code: [u8; 2],
+    pub country_code: u8,
+    pub revision: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeaderWarning {
+    UnusualPiConfig(PiBsdDomainConfig),
+    EntrypointNotKseg0(u32),
+    UnknownCategoryCode(u8),
+    UnknownCountryCode(u8),
+}
+
+impl Display for HeaderWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HeaderWarning::UnusualPiConfig(config) => {
+                write!(f, "non-standard PI BSD domain 1 config ({})", config)
+            }
+            HeaderWarning::EntrypointNotKseg0(entrypoint) => {
+                write!(f, "entrypoint 0x{:08X} is not in KSEG0", entrypoint)
+            }
+            HeaderWarning::UnknownCategoryCode(code) => {
+                write!(f, "unknown category code 0x{:02X}", code)
+            }
+            HeaderWarning::UnknownCountryCode(code) => {
+                write!(f, "unknown country code 0x{:02X}", code)
+            }
+        }
+    }
+}
+
+/// Decode JIS X 0201: ASCII plus halfwidth katakana in 0xA1-0xDF
+fn decode_jis_x_0201(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&b| match b {
+            0x20..=0x7E => b as char,
+            0xA1..=0xDF => char::from_u32(0xFF61 + (b - 0xA1) as u32).unwrap(),
+            _ => ' ',
+        })
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+impl RomHeader {
+    /// Parse the header from the start of a big-endian rom
+    pub fn parse(rom_bytes: &[u8]) -> Result<RomHeader> {
+        if rom_bytes.len() < HEADER_SIZE {
+            return Err(AnalyserError::TruncatedRom {
+                size: rom_bytes.len(),
+                expected: HEADER_SIZE,
+            });
+        }
+
+        Ok(RomHeader {
+            pi_bsd_dom1_config: PiBsdDomainConfig::from_word(read_be_word(&rom_bytes[0x00..])),
+            clock_rate: read_be_word(&rom_bytes[0x04..]),
+            entrypoint: read_be_word(&rom_bytes[0x08..]),
+            libultra_release: read_be_word(&rom_bytes[0x0C..]),
+            crc1: read_be_word(&rom_bytes[0x10..]),
+            crc2: read_be_word(&rom_bytes[0x14..]),
+            name: decode_jis_x_0201(&rom_bytes[0x20..0x34]),
+            category_code: rom_bytes[0x3B],
+            game_code: [rom_bytes[0x3C], rom_bytes[0x3D]],
+            country_code: rom_bytes[0x3E],
+            revision: rom_bytes[0x3F],
+        })
+    }
+
+    /// The four-character code used to identify a release, e.g. `NSME`
+    pub fn full_game_code(&self) -> String {
+        [
+            self.category_code,
+            self.game_code[0],
+            self.game_code[1],
+            self.country_code,
+        ]
+        .iter()
+        .map(|&b| if b.is_ascii_graphic() { b as char } else { '?' })
+        .collect()
+    }
+
+    /// Version of libultra the rom was built with, e.g. `2.0L`, if the field is in the usual format
+    pub fn libultra_version(&self) -> Option<String> {
+        let [_, _, version, revision] = self.libultra_release.to_be_bytes();
+        if version == 0 || !revision.is_ascii_uppercase() {
+            return None;
+        }
+        Some(format!("{}.{}{}", version / 10, version % 10, revision as char))
+    }
+
+    pub fn category(&self) -> Option<&'static str> {
+        Some(match self.category_code {
+            b'N' => "Game Pak",
+            b'D' => "64DD disk",
+            b'C' => "Expandable game (Game Pak part)",
+            b'E' => "Expandable game (64DD part)",
+            b'Z' => "Aleck64 Game Pak",
+            _ => return None,
+        })
+    }
+
+    pub fn country(&self) -> Option<&'static str> {
+        Some(match self.country_code {
+            b'7' => "Beta",
+            b'A' => "Asian (NTSC)",
+            b'B' => "Brazilian",
+            b'C' => "Chinese",
+            b'D' => "German",
+            b'E' => "North American",
+            b'F' => "French",
+            b'G' => "Gateway 64 (NTSC)",
+            b'H' => "Dutch",
+            b'I' => "Italian",
+            b'J' => "Japanese",
+            b'K' => "Korean",
+            b'L' => "Gateway 64 (PAL)",
+            b'N' => "Canadian",
+            b'P' => "European (basic spec.)",
+            b'S' => "Spanish",
+            b'U' => "Australian",
+            b'W' => "Scandinavian",
+            b'X' | b'Y' | b'Z' => "European",
+            _ => return None,
+        })
+    }
+
+    /// Whether the country code is for a PAL region
+    pub fn is_pal(&self) -> bool {
+        matches!(
+            self.country_code,
+            b'D' | b'F' | b'H' | b'I' | b'L' | b'P' | b'S' | b'U' | b'W' | b'X' | b'Y' | b'Z'
+        )
+    }
+
+    /// Fields that look inconsistent with a normal retail rom
+    pub fn warnings(&self) -> Vec<HeaderWarning> {
+        let mut warnings = Vec::new();
+
+        if self.pi_bsd_dom1_config != STANDARD_PI_BSD_DOM1_CONFIG {
+            warnings.push(HeaderWarning::UnusualPiConfig(self.pi_bsd_dom1_config));
+        }
+        if !(KSEG0_START..KSEG1_START).contains(&self.entrypoint) {
+            warnings.push(HeaderWarning::EntrypointNotKseg0(self.entrypoint));
+        }
+        if self.category().is_none() {
+            warnings.push(HeaderWarning::UnknownCategoryCode(self.category_code));
+        }
+        if self.country().is_none() {
+            warnings.push(HeaderWarning::UnknownCountryCode(self.country_code));
+        }
+
+        warnings
+    }
+}
@@ -1,50 +1,136 @@
 use crc;
+use std::ffi::OsStr;
+use std::fs;
 use std::io;
+use std::path::Path;
 
-// #[derive(Debug, Clone)]
+use crate::checksum::BootChecksum;
+use crate::error::{AnalyserError, Result};
+use crate::header::HEADER_SIZE;
+use crate::scan::{self, PatternId, ScannerBuilder};
+use crate::utils::*;
+use crate::IPL3_END;
+
+const IPL3_SIZE: usize = IPL3_END - HEADER_SIZE;
+
+const CKSUM_ALG: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_CKSUM);
+const CRC32_ALG: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+
+/// IPL3s are compared against fingerprints in blocks of this size
+const FINGERPRINT_BLOCK: usize = 0x40;
+/// Below this fraction of identical bytes or blocks, the most similar IPL3 is too different to be worth reporting
+const MIN_NEAREST_SIMILARITY: f64 = 0.5;
+
+/// CRC-32/ISO-HDLC of each block of an IPL3, which a database entry can store instead of a whole dump so that unknown
+/// IPL3s can be matched to it as the nearest one
+pub type Fingerprint = [u32; IPL3_SIZE / FINGERPRINT_BLOCK];
+
+/// What the IPL3 runs on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    Cartridge,
+    DiskDrive,
+    Aleck64,
+    IQue,
+    Homebrew,
+}
+
+#[derive(Debug, Clone)]
 pub struct CICInfo {
-    checksum: u32,
+    /// CRC-32/CKSUM of the IPL3
+    cksum: Option<u32>,
+    /// CRC-32/ISO-HDLC of the IPL3, which is what most other tools use
+    crc32: Option<u32>,
+    /// Bytes only found in this IPL3, for ones that are built from source and so have no fixed CRC
+    signature: Option<&'static [u8]>,
+    /// Block checksums of a dump, to find the nearest entry to an IPL3 that matches none exactly
+    fingerprint: Option<&'static Fingerprint>,
     ntsc_name: &'static str,
     pal_name: &'static str,
     entrypoint_offset: u32,
+    boot_checksum: Option<BootChecksum>,
+    platform: Platform,
 }
 
 impl CICInfo {
     const fn new(
-        checksum: u32,
+        cksum: Option<u32>,
+        crc32: Option<u32>,
         ntsc_name: &'static str,
         pal_name: &'static str,
         entrypoint_offset: u32,
+        boot_checksum: Option<BootChecksum>,
+        platform: Platform,
     ) -> CICInfo {
         CICInfo {
-            checksum,
+            cksum,
+            crc32,
+            signature: None,
+            fingerprint: None,
             ntsc_name,
             pal_name,
             entrypoint_offset,
+            boot_checksum,
+            platform,
         }
     }
 
-    pub const fn get_from_crc(crc: u32) -> io::Result<CICInfo> {
-        Ok(match crc {
-            0xD1F2D592 => CICInfo::new(0xD1F2D592, "6102", "7101", 0x000000),
-            0x27DF61E2 => CICInfo::new(0x27DF61E2, "6103", "7103", 0x100000),
-            0x229F516C => CICInfo::new(0x229F516C, "6105", "7105", 0x000000),
-            0xA0DD69F7 => CICInfo::new(0xA0DD69F7, "6106", "7106", 0x200000),
-            0x0013579C => CICInfo::new(0x0013579C, "6101", "-", 0x000000),
-            0xDAB442CD => CICInfo::new(0xDAB442CD, "-", "7102", 0x80000480),
-            _ => CICInfo::new(0, "unk", "unk", 0x000000),
-        })
+    const fn with_signature(mut self, signature: &'static [u8]) -> CICInfo {
+        self.signature = Some(signature);
+        self
+    }
+
+    /// For the entries that have no CRC, once a dump of them has been fingerprinted with `fingerprint`
+    #[allow(dead_code)]
+    const fn with_fingerprint(mut self, fingerprint: &'static Fingerprint) -> CICInfo {
+        self.fingerprint = Some(fingerprint);
+        self
+    }
+
+    /// Look up an IPL3 by its CRC-32/CKSUM
+    pub fn get_from_crc(crc: u32) -> Option<&'static CICInfo> {
+        IPL3_DATABASE.iter().find(|cic| cic.cksum == Some(crc))
+    }
+
+    /// Look up an IPL3 by its CRC-32/ISO-HDLC
+    pub fn get_from_crc32(crc: u32) -> Option<&'static CICInfo> {
+        IPL3_DATABASE.iter().find(|cic| cic.crc32 == Some(crc))
+    }
+
+    /// Look up an IPL3 by either of its names
+    pub fn get_from_name(name: &str) -> Option<&'static CICInfo> {
+        IPL3_DATABASE
+            .iter()
+            .find(|cic| cic.ntsc_name == name || cic.pal_name == name)
     }
 
     pub fn name(&self) -> String {
         if self.ntsc_name == "-" {
-            format!("{}", self.pal_name)
+            self.pal_name.to_string()
         } else if self.pal_name == "-" {
-            format!("{}", self.ntsc_name)
+            self.ntsc_name.to_string()
         } else {
             format!("{} / {}", self.ntsc_name, self.pal_name)
         }
-        .to_string()
+    }
+
+    /// The name of the CIC for the rom's video region, falling back to the other region's name if there isn't one
+    pub fn regional_name(&self, is_pal: bool) -> &'static str {
+        match (is_pal, self.ntsc_name, self.pal_name) {
+            (true, ntsc_name, "-") => ntsc_name,
+            (true, _, pal_name) => pal_name,
+            (false, "-", pal_name) => pal_name,
+            (false, ntsc_name, _) => ntsc_name,
+        }
+    }
+
+    pub const fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    /// The algorithm this CIC uses to verify the boot segment, if known
+    pub const fn boot_checksum(&self) -> Option<BootChecksum> {
+        self.boot_checksum
     }
 
     const fn entrypoint_offset(&self) -> u32 {
@@ -57,19 +143,246 @@ impl CICInfo {
         if offset >= 0x80000000 {
             offset
         } else {
-            header_entrypoint - offset
+            header_entrypoint.wrapping_sub(offset)
         }
     }
 }
 
-pub fn identify(mut reader: impl io::Read) -> io::Result<CICInfo> {
-    let mut ipl3 = [0u8; IPL3_END - 0x40];
+/// Every known IPL3. Entries without any CRC or signature can only be identified from reference dumps (see
+/// `load_references`); an entry with a `fingerprint` can also be found as the nearest match without one.
+#[rustfmt::skip]
+static IPL3_DATABASE: &[CICInfo] = &[
+    //           CRC-32/CKSUM       CRC-32/ISO-HDLC    NTSC         PAL     Entrypoint  Boot checksum                Platform
+    CICInfo::new(Some(0xD1F2D592), Some(0x90BB6CB5), "6102",      "7101", 0x000000,   Some(BootChecksum::Cic6102), Platform::Cartridge),
+    CICInfo::new(Some(0x27DF61E2), Some(0x0B050EE0), "6103",      "7103", 0x100000,   Some(BootChecksum::Cic6103), Platform::Cartridge),
+    CICInfo::new(Some(0x229F516C), Some(0x98BC2C86), "6105",      "7105", 0x000000,   Some(BootChecksum::Cic6105), Platform::Cartridge),
+    CICInfo::new(Some(0xA0DD69F7), Some(0xACC8580A), "6106",      "7106", 0x200000,   Some(BootChecksum::Cic6106), Platform::Cartridge),
+    CICInfo::new(Some(0x0013579C), Some(0x6170A4A1), "6101",      "-",    0x000000,   Some(BootChecksum::Cic6102), Platform::Cartridge),
+    CICInfo::new(Some(0xDAB442CD), Some(0x009E9EA3), "-",         "7102", 0x80000480, Some(BootChecksum::Cic6102), Platform::Cartridge),
+    CICInfo::new(None,             Some(0x0E018159), "8303",      "-",    0x000000,   None,                        Platform::DiskDrive),
+    CICInfo::new(None,             None,             "5167",      "-",    0x000000,   None,                        Platform::DiskDrive),
+    CICInfo::new(None,             None,             "5101",      "-",    0x000000,   None,                        Platform::Aleck64),
+    CICInfo::new(None,             None,             "iQue-6102", "-",    0x000000,   None,                        Platform::IQue),
+    CICInfo::new(None,             None,             "iQue-6103", "-",    0x100000,   None,                        Platform::IQue),
+    CICInfo::new(None,             None,             "iQue-6105", "-",    0x000000,   None,                        Platform::IQue),
+    CICInfo::new(None,             None,             "iQue-6106", "-",    0x200000,   None,                        Platform::IQue),
+    CICInfo::new(None,             None,             "libdragon", "-",    0x000000,   None,                        Platform::Homebrew)
+        .with_signature(b"Libdragon IPL3"),
+];
+
+/// The signatures of the entries in the database that have one, as registered with the shared scanner
+#[derive(Debug)]
+pub struct Signatures(Vec<(&'static CICInfo, PatternId)>);
+
+impl Signatures {
+    pub(crate) fn register(builder: &mut ScannerBuilder) -> Self {
+        Self(
+            IPL3_DATABASE
+                .iter()
+                .filter_map(|cic| cic.signature.map(|signature| (cic, builder.register(signature, 1))))
+                .collect(),
+        )
+    }
+}
+
+/// The first database entry whose signature is in `ipl3`
+fn find_signature(ipl3: &[u8]) -> Option<&'static CICInfo> {
+    let shared = scan::shared();
+    let results = shared.scanner.scan(ipl3, 0..ipl3.len());
+    shared
+        .ipl3
+        .0
+        .iter()
+        .find(|(_, pattern)| !results.hits(*pattern).is_empty())
+        .map(|&(cic, _)| cic)
+}
+
+/// A dump of a known IPL3, used to identify variants without a fixed CRC and to find the nearest match for unknown ones
+#[derive(Debug, Clone)]
+pub struct ReferenceIpl3 {
+    cic: &'static CICInfo,
+    bytes: Vec<u8>,
+}
+
+/// Load every `<name>.bin` in a directory as a reference dump of the IPL3 of that name (e.g. `5101.bin`). Each file can
+/// be either the bare IPL3 or a whole rom.
+pub fn load_references(dir: &Path) -> Result<Vec<ReferenceIpl3>> {
+    let mut references = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension() != Some(OsStr::new("bin")) {
+            continue;
+        }
+
+        let name = path.file_stem().and_then(OsStr::to_str).unwrap_or_default();
+        let cic = CICInfo::get_from_name(name).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{}: unknown IPL3 name `{}`", path.display(), name),
+            )
+        })?;
+
+        let mut bytes = fs::read(&path)?;
+        if bytes.len() != IPL3_SIZE {
+            if bytes.len() < IPL3_END {
+                return Err(AnalyserError::TruncatedRom {
+                    size: bytes.len(),
+                    expected: IPL3_END,
+                });
+            }
+            bytes.truncate(IPL3_END);
+            let endian = get_endian(&bytes)?;
+            reend_array(&mut bytes, &endian)?;
+            bytes.drain(..HEADER_SIZE);
+        }
+
+        references.push(ReferenceIpl3 { cic, bytes });
+    }
+
+    Ok(references)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Ipl3Match {
+    Crc,
+    Signature,
+    /// Byte-identical to a reference dump
+    Reference,
+    /// Nothing matched exactly, but this was the most similar reference dump or database fingerprint (fraction of
+    /// identical bytes or blocks)
+    Nearest(f64),
+    Unknown,
+}
+
+#[derive(Debug, Clone)]
+pub struct Ipl3Identification {
+    /// CRC-32/CKSUM of the IPL3
+    pub cksum: u32,
+    /// CRC-32/ISO-HDLC of the IPL3
+    pub crc32: u32,
+    /// `None` if `method` is `Ipl3Match::Unknown`
+    pub cic: Option<&'static CICInfo>,
+    pub method: Ipl3Match,
+}
+
+impl Ipl3Identification {
+    /// The CIC, only if the IPL3 was matched exactly
+    pub fn exact(&self) -> Option<&'static CICInfo> {
+        match self.method {
+            Ipl3Match::Nearest(_) | Ipl3Match::Unknown => None,
+            _ => self.cic,
+        }
+    }
+
+    /// Checksum algorithm, only if the IPL3 was matched exactly
+    pub fn boot_checksum(&self) -> Option<BootChecksum> {
+        self.exact().and_then(CICInfo::boot_checksum)
+    }
+
+    /// Correct the entrypoint for the CIC, leaving it alone if the IPL3 wasn't matched exactly
+    pub fn correct_entrypoint(&self, header_entrypoint: u32) -> u32 {
+        self.exact()
+            .map_or(header_entrypoint, |cic| cic.correct_entrypoint(header_entrypoint))
+    }
+}
+
+/// Fraction of bytes that are identical between two IPL3s
+fn similarity(a: &[u8], b: &[u8]) -> f64 {
+    let same = a.iter().zip(b).filter(|(x, y)| x == y).count();
+    same as f64 / IPL3_SIZE as f64
+}
+
+/// The fingerprint of an IPL3, to store in the database
+pub fn fingerprint(ipl3: &[u8]) -> Fingerprint {
+    let mut fingerprint = [0; IPL3_SIZE / FINGERPRINT_BLOCK];
+    for (crc, block) in fingerprint.iter_mut().zip(ipl3.chunks_exact(FINGERPRINT_BLOCK)) {
+        *crc = CRC32_ALG.checksum(block);
+    }
+    fingerprint
+}
+
+/// The most similar IPL3 out of the reference dumps and the database entries with a fingerprint, with its similarity,
+/// if it is at least `MIN_NEAREST_SIMILARITY`
+fn nearest(ipl3: &[u8], references: &[ReferenceIpl3]) -> Option<(&'static CICInfo, f64)> {
+    let blocks = fingerprint(ipl3);
+    let fingerprinted = IPL3_DATABASE.iter().filter_map(|cic| {
+        cic.fingerprint.map(|fingerprint| {
+            let same = blocks.iter().zip(fingerprint).filter(|(x, y)| x == y).count();
+            (cic, same as f64 / blocks.len() as f64)
+        })
+    });
+
+    references
+        .iter()
+        .map(|reference| (reference.cic, similarity(ipl3, &reference.bytes)))
+        .chain(fingerprinted)
+        .max_by(|(_, x), (_, y)| x.total_cmp(y))
+        .filter(|&(_, score)| score >= MIN_NEAREST_SIMILARITY)
+}
+
+/// Identify the CIC from the IPL3, which is read from the end of the header onwards
+pub fn identify(
+    mut reader: impl io::Read,
+    references: &[ReferenceIpl3],
+) -> io::Result<Ipl3Identification> {
+    let mut ipl3 = [0u8; IPL3_SIZE];
     reader.read_exact(&mut ipl3)?;
 
-    const CRC_ALG: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_CKSUM);
+    let cksum = CKSUM_ALG.checksum(&ipl3);
+    let crc32 = CRC32_ALG.checksum(&ipl3);
+
+    let (cic, method) = if let Some(cic) =
+        CICInfo::get_from_crc(cksum).or_else(|| CICInfo::get_from_crc32(crc32))
+    {
+        (Some(cic), Ipl3Match::Crc)
+    } else if let Some(cic) = find_signature(&ipl3) {
+        (Some(cic), Ipl3Match::Signature)
+    } else if let Some(reference) = references.iter().find(|reference| reference.bytes == ipl3) {
+        (Some(reference.cic), Ipl3Match::Reference)
+    } else if let Some((cic, score)) = nearest(&ipl3, references) {
+        (Some(cic), Ipl3Match::Nearest(score))
+    } else {
+        (None, Ipl3Match::Unknown)
+    };
 
-    let hash = CRC_ALG.checksum(&ipl3);
+    Ok(Ipl3Identification {
+        cksum,
+        crc32,
+        cic,
+        method,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reference(name: &str, bytes: Vec<u8>) -> ReferenceIpl3 {
+        ReferenceIpl3 {
+            cic: CICInfo::get_from_name(name).unwrap(),
+            bytes,
+        }
+    }
 
-    CICInfo::get_from_crc(hash)
+    #[test]
+    fn nearest_needs_a_close_enough_match() {
+        let dump = (0..IPL3_SIZE).map(|i| (i * 7 + i / 0x100) as u8).collect::<Vec<_>>();
+        let references = [reference("5101", dump.clone())];
+
+        let identification = identify(dump.as_slice(), &references).unwrap();
+        assert_eq!(identification.method, Ipl3Match::Reference);
+        assert_eq!(identification.cic.unwrap().name(), "5101");
+
+        let mut patched = dump.clone();
+        patched[0x100..0x140].fill(0);
+        let identification = identify(patched.as_slice(), &references).unwrap();
+        assert!(matches!(identification.method, Ipl3Match::Nearest(score) if score > 0.9));
+        assert!(identification.exact().is_none());
+
+        let unrelated = dump.iter().map(|byte| !byte).collect::<Vec<_>>();
+        let identification = identify(unrelated.as_slice(), &references).unwrap();
+        assert_eq!(identification.method, Ipl3Match::Unknown);
+        assert!(identification.cic.is_none());
+    }
 }
-    
\ No newline at end of file
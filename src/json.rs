@@ -0,0 +1,627 @@
+//! A stable, versioned JSON schema for `RomReport`. The structs here mirror the report rather than deriving
+//! `Serialize` on it directly, so that internal changes don't silently change the output. Any change that would break
+//! an existing consumer must bump `FORMAT_VERSION`; fields that are only added don't, so consumers should ignore ones
+//! they don't know.
+//!
+//! `write_diff` writes a `JsonDiff`, which embeds the `JsonReport` of each rom and shares its version.
+
+use std::collections::BTreeMap;
+use std::io;
+
+use serde::Serialize;
+
+use crate::compiler::{CompilerEvidence, CompilerReport};
+use crate::compression::encode::SegmentEncoders;
+use crate::diff::RomDiff;
+use crate::disk::DiskLayout;
+use crate::dmatable::DmaTable;
+use crate::error::Result;
+use crate::findcode::trace::TraceEvent;
+use crate::findcode::RomRegion;
+use crate::ipl3::{Ipl3Identification, Ipl3Match, Platform};
+use crate::ngrams::{self, NgramTable};
+use crate::rommap::{self, Category, RomMap};
+use crate::segments::SegmentCode;
+use crate::RomReport;
+
+pub const FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize)]
+pub struct JsonReport {
+    pub format_version: u32,
+    pub tool_version: &'static str,
+    pub header: Option<JsonHeader>,
+    pub cic: Option<JsonCic>,
+    pub entrypoint: Option<u32>,
+    pub checksum: Option<JsonChecksum>,
+    pub disk: Option<JsonDisk>,
+    pub function_count_estimate: Option<usize>,
+    pub code_regions: Option<Vec<JsonRegion>>,
+    pub compiler: Option<JsonCompiler>,
+    pub compressed_segments: Option<Vec<JsonCompressedSegment>>,
+    pub segment_code: Option<Vec<JsonSegmentCode>>,
+    pub encoders: Option<Vec<JsonSegmentEncoders>>,
+    pub dma_tables: Option<Vec<JsonDmaTable>>,
+    pub ngrams: Vec<JsonNgramTable>,
+    pub rom_map: Option<JsonRomMap>,
+    pub high_entropy_spans: Option<Vec<JsonEntropySpan>>,
+    pub trace: Vec<JsonTraceEvent>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonHeader {
+    pub name: String,
+    pub game_code: String,
+    pub category: Option<&'static str>,
+    pub country: Option<&'static str>,
+    pub revision: u8,
+    pub pi_bsd_dom1_config: u32,
+    pub clock_rate: u32,
+    pub entrypoint: u32,
+    pub libultra_version: Option<String>,
+    pub crc1: u32,
+    pub crc2: u32,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonCic {
+    /// `None` if the IPL3 is unknown
+    pub name: Option<String>,
+    pub platform: Option<&'static str>,
+    /// One of `crc`, `signature`, `reference`, `nearest` or `unknown`
+    pub method: &'static str,
+    /// Fraction of identical bytes, only for `nearest`
+    pub similarity: Option<f64>,
+    pub cksum: u32,
+    pub crc32: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonChecksum {
+    pub header: [u32; 2],
+    pub calculated: [u32; 2],
+    pub matches: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonDisk {
+    pub region: u32,
+    pub development: bool,
+    pub disk_type: u8,
+    pub ipl_load_blocks: u16,
+    pub ipl_load_address: u32,
+    pub rom_end_lba: u32,
+    pub lba_count: u32,
+    /// Image offsets of the IPL loaded at boot
+    pub boot_start: usize,
+    pub boot_end: usize,
+    /// Image offsets of everything after the boot area
+    pub data_start: usize,
+    pub data_end: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonRegion {
+    pub rom_start: usize,
+    pub rom_end: usize,
+    pub has_rsp: bool,
+    /// Address the region is loaded to, if it is in the boot segment
+    pub vram: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonEvidence {
+    pub b_count: i32,
+    pub j_count: i32,
+    pub float_load_pattern_count: i32,
+    pub isolated_mtc1_count: i32,
+    pub break_6_pattern_count: i32,
+    pub other_break_6_count: i32,
+    pub break_7_pattern_count: i32,
+    pub other_break_7_count: i32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonRegionCompiler {
+    pub rom_start: usize,
+    pub rom_end: usize,
+    pub evidence: JsonEvidence,
+    pub possible_compilers: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonCompiler {
+    pub regions: Vec<JsonRegionCompiler>,
+    pub total: JsonEvidence,
+    pub possible_compilers: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonCompressedSegment {
+    pub algorithm: String,
+    pub rom_start: usize,
+    /// End of the compressed data, not including any padding after it
+    pub rom_end: usize,
+    pub decompressed_size: usize,
+    /// Compressed size as a fraction of the decompressed size
+    pub ratio: f64,
+}
+
+/// Code found inside a decompressed segment. The addresses in `code_regions` and `compiler` are offsets into the
+/// decompressed data, not rom addresses.
+#[derive(Debug, Serialize)]
+pub struct JsonSegmentCode {
+    pub algorithm: String,
+    /// Rom address of the compressed segment
+    pub rom_start: usize,
+    pub decompressed_size: usize,
+    pub function_count_estimate: Option<usize>,
+    pub code_regions: Vec<JsonRegion>,
+    pub compiler: Option<JsonCompiler>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonSegmentEncoders {
+    pub algorithm: String,
+    /// Rom address of the compressed segment
+    pub rom_start: usize,
+    pub compressed_size: usize,
+    /// Strategies that reproduce the segment exactly
+    pub exact: Vec<&'static str>,
+    pub strategies: Vec<JsonEncoderMatch>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonEncoderMatch {
+    pub strategy: &'static str,
+    /// Size of the re-encoded segment
+    pub size: usize,
+    /// Number of bytes at the start that are the same as the original
+    pub matching_prefix: usize,
+    pub exact: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonDmaTable {
+    pub rom_start: usize,
+    pub rom_end: usize,
+    /// Size of an entry in bytes
+    pub entry_size: usize,
+    /// Position of the start offset in an entry; the end offset is the next word
+    pub start_offset: usize,
+    /// Number of entries that start or end on a known boundary
+    pub hits: usize,
+    pub entries: Vec<JsonDmaEntry>,
+}
+
+/// `start` and `end` are both `None` for a removed file, and `end` is `None` for one stored with an end of 0
+#[derive(Debug, Serialize)]
+pub struct JsonDmaEntry {
+    pub start: Option<usize>,
+    pub end: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonNgram {
+    pub instructions: Vec<String>,
+    pub count: usize,
+    pub relative_frequency: f64,
+    pub first_rom_addr: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonNgramTable {
+    pub n: usize,
+    /// `[rom_start, rom_end]` of the region the table covers, or `None` for every region
+    pub region: Option<[usize; 2]>,
+    pub region_count: usize,
+    pub instruction_count: usize,
+    pub gram_count: usize,
+    pub entries: Vec<JsonNgram>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonRun {
+    /// One of `header`, `ipl3`, `cpu_code`, `rsp_code`, `compressed`, `fill` or `data`
+    pub category: &'static str,
+    pub rom_start: usize,
+    pub rom_end: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonRomMap {
+    pub block_size: usize,
+    pub runs: Vec<JsonRun>,
+    /// Fraction of the rom in each category
+    pub coverage: BTreeMap<&'static str, f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonEntropySpan {
+    pub rom_start: usize,
+    pub rom_end: usize,
+    /// Bits per byte
+    pub entropy: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonRegionDiff {
+    /// `[rom_start, rom_end]` in each rom, `None` if the region isn't in that rom
+    pub a: Option<[usize; 2]>,
+    pub b: Option<[usize; 2]>,
+    /// Fraction of normalised instructions in common, if the region is in both
+    pub similarity: Option<f64>,
+    /// Some of `moved`, `grew`, `shrank`, `changed`, or one of `unchanged`, `appeared` or `vanished`
+    pub changes: Vec<&'static str>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonSegmentDiff {
+    pub algorithm: String,
+    pub a: Option<usize>,
+    pub b: Option<usize>,
+    pub a_decompressed_size: Option<usize>,
+    pub b_decompressed_size: Option<usize>,
+    pub changes: Vec<&'static str>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonDiff {
+    pub format_version: u32,
+    pub tool_version: &'static str,
+    pub a: JsonReport,
+    pub b: JsonReport,
+    pub regions: Vec<JsonRegionDiff>,
+    pub compressed_segments: Vec<JsonSegmentDiff>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonRejected {
+    pub rom_addr: usize,
+    pub word: u32,
+    /// Identifier of the rule that rejected the instruction, e.g. `invalid_cache_op`
+    pub rule: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonTraceEvent {
+    /// Kind of decision, e.g. `region_start` or `not_merged`
+    pub event: &'static str,
+    /// `[start, end)` of the rom addresses the decision is about
+    pub span: [usize; 2],
+    /// The instructions whose rejection caused the decision
+    pub rejected: Vec<JsonRejected>,
+    pub description: String,
+}
+
+impl From<&CompilerEvidence> for JsonEvidence {
+    fn from(evidence: &CompilerEvidence) -> Self {
+        JsonEvidence {
+            b_count: evidence.b_count,
+            j_count: evidence.j_count,
+            float_load_pattern_count: evidence.float_load_pattern_count,
+            isolated_mtc1_count: evidence.isolated_mtc1_count,
+            break_6_pattern_count: evidence.break_6_pattern_count,
+            other_break_6_count: evidence.other_break_6_count,
+            break_7_pattern_count: evidence.break_7_pattern_count,
+            other_break_7_count: evidence.other_break_7_count,
+        }
+    }
+}
+
+fn sorted_names<T: ToString>(items: impl IntoIterator<Item = T>) -> Vec<String> {
+    let mut names = items
+        .into_iter()
+        .map(|item| item.to_string())
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+fn platform_name(platform: Platform) -> &'static str {
+    match platform {
+        Platform::Cartridge => "cartridge",
+        Platform::DiskDrive => "disk_drive",
+        Platform::Aleck64 => "aleck64",
+        Platform::IQue => "ique",
+        Platform::Homebrew => "homebrew",
+    }
+}
+
+fn cic(identification: &Ipl3Identification) -> JsonCic {
+    let (method, similarity) = match identification.method {
+        Ipl3Match::Crc => ("crc", None),
+        Ipl3Match::Signature => ("signature", None),
+        Ipl3Match::Reference => ("reference", None),
+        Ipl3Match::Nearest(score) => ("nearest", Some(score)),
+        Ipl3Match::Unknown => ("unknown", None),
+    };
+
+    JsonCic {
+        name: identification.cic.map(|cic| cic.name()),
+        platform: identification.cic.map(|cic| platform_name(cic.platform())),
+        method,
+        similarity,
+        cksum: identification.cksum,
+        crc32: identification.crc32,
+    }
+}
+
+fn disk(disk: &DiskLayout) -> JsonDisk {
+    JsonDisk {
+        region: disk.system.region,
+        development: disk.system.development,
+        disk_type: disk.system.disk_type,
+        ipl_load_blocks: disk.system.ipl_load_blocks,
+        ipl_load_address: disk.system.ipl_load_address,
+        rom_end_lba: disk.rom_end_lba(),
+        lba_count: disk.lba_count(),
+        boot_start: disk.boot_area().start,
+        boot_end: disk.boot_area().end,
+        data_start: disk.data_area().start,
+        data_end: disk.data_area().end,
+    }
+}
+
+fn compiler(report: &CompilerReport) -> JsonCompiler {
+    JsonCompiler {
+        regions: report
+            .regions
+            .iter()
+            .map(|region| JsonRegionCompiler {
+                rom_start: region.rom_start,
+                rom_end: region.rom_end,
+                evidence: (&region.evidence).into(),
+                possible_compilers: sorted_names(&region.possible_compilers),
+            })
+            .collect(),
+        total: (&report.total).into(),
+        possible_compilers: sorted_names(&report.possible_compilers),
+    }
+}
+
+fn segment_code(code: &SegmentCode) -> JsonSegmentCode {
+    JsonSegmentCode {
+        algorithm: code.segment.algorithm().to_string(),
+        rom_start: code.segment.rom_start(),
+        decompressed_size: code.segment.decompressed_size(),
+        function_count_estimate: code.function_count_estimate,
+        code_regions: code
+            .code_regions
+            .iter()
+            .map(|region| JsonRegion {
+                rom_start: region.rom_start(),
+                rom_end: region.rom_end(),
+                has_rsp: region.has_rsp(),
+                vram: None,
+            })
+            .collect(),
+        compiler: code.compiler.as_ref().map(compiler),
+    }
+}
+
+fn segment_encoders(encoders: &SegmentEncoders) -> JsonSegmentEncoders {
+    JsonSegmentEncoders {
+        algorithm: encoders.segment.algorithm().to_string(),
+        rom_start: encoders.segment.rom_start(),
+        compressed_size: encoders.segment.compressed_size(),
+        exact: encoders.exact().map(|strategy| strategy.name()).collect(),
+        strategies: encoders
+            .matches
+            .iter()
+            .map(|encoder| JsonEncoderMatch {
+                strategy: encoder.strategy.name(),
+                size: encoder.size,
+                matching_prefix: encoder.matching_prefix,
+                exact: encoder.exact,
+            })
+            .collect(),
+    }
+}
+
+fn dma_table(table: &DmaTable) -> JsonDmaTable {
+    JsonDmaTable {
+        rom_start: table.rom_start,
+        rom_end: table.rom_end,
+        entry_size: table.layout.entry_size,
+        start_offset: table.layout.start_offset,
+        hits: table.hits,
+        entries: table
+            .entries
+            .iter()
+            .map(|entry| JsonDmaEntry {
+                start: entry.start(),
+                end: entry.end(),
+            })
+            .collect(),
+    }
+}
+
+fn rom_map(map: &RomMap) -> JsonRomMap {
+    JsonRomMap {
+        block_size: rommap::BLOCK_SIZE,
+        runs: map
+            .runs
+            .iter()
+            .map(|run| JsonRun {
+                category: run.category.name(),
+                rom_start: run.rom_start,
+                rom_end: run.rom_end,
+            })
+            .collect(),
+        coverage: Category::ALL
+            .iter()
+            .map(|&category| (category.name(), map.coverage(category)))
+            .collect(),
+    }
+}
+
+fn trace_event(event: &TraceEvent) -> JsonTraceEvent {
+    let span = event.span();
+    JsonTraceEvent {
+        event: event.name(),
+        span: [span.start, span.end],
+        rejected: event
+            .rejected()
+            .into_iter()
+            .map(|rejected| JsonRejected {
+                rom_addr: rejected.rom_addr,
+                word: rejected.word,
+                rule: rejected.rejection.name(),
+            })
+            .collect(),
+        description: event.to_string(),
+    }
+}
+
+fn ngram_table(table: &NgramTable) -> JsonNgramTable {
+    JsonNgramTable {
+        n: table.n,
+        region: table
+            .region
+            .as_ref()
+            .map(|region| [region.rom_start(), region.rom_end()]),
+        region_count: table.region_count,
+        instruction_count: table.instruction_count,
+        gram_count: table.gram_count,
+        entries: table
+            .entries
+            .iter()
+            .map(|entry| JsonNgram {
+                instructions: entry.instructions.iter().map(ngrams::mnemonic).collect(),
+                count: entry.count,
+                relative_frequency: table.relative_frequency(entry),
+                first_rom_addr: entry.first_rom_addr,
+            })
+            .collect(),
+    }
+}
+
+impl From<&RomReport> for JsonReport {
+    fn from(report: &RomReport) -> Self {
+        JsonReport {
+            format_version: FORMAT_VERSION,
+            tool_version: env!("CARGO_PKG_VERSION"),
+            header: report.header.as_ref().map(|header| JsonHeader {
+                name: header.name.clone(),
+                game_code: header.full_game_code(),
+                category: header.category(),
+                country: header.country(),
+                revision: header.revision,
+                pi_bsd_dom1_config: header.pi_bsd_dom1_config.to_word(),
+                clock_rate: header.clock_rate,
+                entrypoint: header.entrypoint,
+                libultra_version: header.libultra_version(),
+                crc1: header.crc1,
+                crc2: header.crc2,
+                warnings: header.warnings().iter().map(ToString::to_string).collect(),
+            }),
+            cic: report.cic.as_ref().map(cic),
+            entrypoint: report.entrypoint,
+            checksum: report.checksum.as_ref().map(|checksum| JsonChecksum {
+                header: [checksum.header.0, checksum.header.1],
+                calculated: [checksum.calculated.0, checksum.calculated.1],
+                matches: checksum.matches(),
+            }),
+            disk: report.disk.as_ref().map(disk),
+            function_count_estimate: report.function_count_estimate,
+            code_regions: report.code_regions.as_ref().map(|regions| {
+                regions
+                    .iter()
+                    .map(|region| JsonRegion {
+                        rom_start: region.rom_start(),
+                        rom_end: region.rom_end(),
+                        has_rsp: region.has_rsp(),
+                        vram: report.boot_vram(region.rom_start()),
+                    })
+                    .collect()
+            }),
+            compiler: report.compiler.as_ref().map(compiler),
+            compressed_segments: report.compressed_segments.as_ref().map(|segments| {
+                segments
+                    .iter()
+                    .map(|seg| JsonCompressedSegment {
+                        algorithm: seg.algorithm().to_string(),
+                        rom_start: seg.rom_start(),
+                        rom_end: seg.rom_end(),
+                        decompressed_size: seg.decompressed_size(),
+                        ratio: seg.ratio(),
+                    })
+                    .collect()
+            }),
+            segment_code: report
+                .segment_code
+                .as_ref()
+                .map(|segments| segments.iter().map(segment_code).collect()),
+            encoders: report
+                .encoders
+                .as_ref()
+                .map(|segments| segments.iter().map(segment_encoders).collect()),
+            dma_tables: report
+                .dma_tables
+                .as_ref()
+                .map(|tables| tables.iter().map(dma_table).collect()),
+            ngrams: report.ngrams.iter().map(ngram_table).collect(),
+            rom_map: report.rom_map.as_ref().map(rom_map),
+            high_entropy_spans: report.high_entropy_spans.as_ref().map(|spans| {
+                spans
+                    .iter()
+                    .map(|span| JsonEntropySpan {
+                        rom_start: span.rom_start,
+                        rom_end: span.rom_end,
+                        entropy: span.entropy,
+                    })
+                    .collect()
+            }),
+            trace: report.trace.iter().map(trace_event).collect(),
+        }
+    }
+}
+
+impl From<&RomDiff> for JsonDiff {
+    fn from(diff: &RomDiff) -> Self {
+        let bounds = |region: &RomRegion| [region.rom_start(), region.rom_end()];
+
+        JsonDiff {
+            format_version: FORMAT_VERSION,
+            tool_version: env!("CARGO_PKG_VERSION"),
+            a: (&diff.a).into(),
+            b: (&diff.b).into(),
+            regions: diff
+                .regions
+                .iter()
+                .map(|region| JsonRegionDiff {
+                    a: region.a.as_ref().map(bounds),
+                    b: region.b.as_ref().map(bounds),
+                    similarity: region.similarity,
+                    changes: region.changes(),
+                })
+                .collect(),
+            compressed_segments: diff
+                .compressed_segments
+                .iter()
+                .map(|seg| JsonSegmentDiff {
+                    algorithm: seg.algorithm.to_string(),
+                    a: seg.a,
+                    b: seg.b,
+                    a_decompressed_size: seg.a_size,
+                    b_decompressed_size: seg.b_size,
+                    changes: seg.changes(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Write the whole report as one JSON document
+pub fn write_report(report: &RomReport, writer: impl io::Write) -> Result<()> {
+    serde_json::to_writer_pretty(writer, &JsonReport::from(report)).map_err(io::Error::from)?;
+    Ok(())
+}
+
+/// Write a comparison of two roms as one JSON document
+pub fn write_diff(diff: &RomDiff, writer: impl io::Write) -> Result<()> {
+    serde_json::to_writer_pretty(writer, &JsonDiff::from(diff)).map_err(io::Error::from)?;
+    Ok(())
+}
@@ -0,0 +1,347 @@
+pub mod batch;
+pub mod checksum;
+pub mod compiler;
+pub mod compression;
+pub mod diff;
+pub mod disk;
+pub mod dmatable;
+pub mod entropy;
+pub mod error;
+pub mod extract;
+pub mod findcode;
+pub mod header;
+pub mod ipl3;
+pub mod json;
+pub mod utils;
+
+pub mod ngrams;
+pub mod rommap;
+pub mod scan;
+pub mod segments;
+pub mod splat;
+
+use std::ops::Range;
+
+use checksum::ChecksumReport;
+use compiler::CompilerReport;
+use compression::encode::SegmentEncoders;
+use compression::CompressedSegment;
+use disk::DiskLayout;
+use dmatable::DmaTable;
+use entropy::{EntropyOptions, EntropySpan};
+use error::{AnalyserError, Result};
+use findcode::trace::{Trace, TraceEvent};
+use findcode::RomRegion;
+use header::{RomHeader, HEADER_SIZE};
+use ipl3::{Ipl3Identification, ReferenceIpl3};
+use ngrams::{NgramOptions, NgramTable};
+use rommap::RomMap;
+use segments::SegmentCode;
+use utils::*;
+
+pub const INSTRUCTION_SIZE: usize = 4;
+pub const WORD_SIZE: usize = 4;
+
+pub const IPL3_END: usize = 0x1000;
+/// Size of the segment the IPL3 loads from `IPL3_END` to the entrypoint, which is also what the boot checksum covers
+pub const BOOT_SEGMENT_SIZE: usize = 0x100000;
+
+/// Selects which analyses `analyse_rom` runs. The header and CIC are always read, since they are cheap. Code regions are
+/// found if they are requested or anything that builds on them is.
+#[derive(Debug, Clone)]
+pub struct AnalysisOptions {
+    /// find code regions
+    pub find_code_regions: bool,
+    /// attempt to determine compiler
+    pub determine_compiler: bool,
+    /// find compressed segments
+    pub find_compressed: bool,
+    /// also search for compression formats with no magic number, which are far more prone to false positives
+    pub magicless_compression: bool,
+    /// also look for code inside decompressed segments, running the function count estimate and compiler heuristics
+    /// there if they are selected
+    pub search_segments: bool,
+    /// re-encode each compressed segment with the known encoders' strategies, to find which one produced it
+    pub identify_encoders: bool,
+    /// find file tables (e.g. Zelda's dmadata) whose offsets land on the compressed segments and code regions found
+    pub find_dma_tables: bool,
+    /// count `jr $ra` instructions as a crude estimate of the number of functions
+    pub estimate_function_count: bool,
+    /// n-gram tables to produce, if any
+    pub ngrams: Option<NgramOptions>,
+    /// classify every block of the rom
+    pub rom_map: bool,
+    /// find high-entropy spans that aren't code or known compressed segments
+    pub entropy: Option<EntropyOptions>,
+    /// dumps of known IPL3s, to identify the ones without a fixed CRC
+    pub ipl3_references: Vec<ReferenceIpl3>,
+    /// treat the input as a bare binary with no header or IPL3, e.g. an extracted overlay
+    pub raw: bool,
+    /// start of the window to search for code and compressed segments
+    pub start: usize,
+    /// end of the window; the end of the rom if `None`
+    pub end: Option<usize>,
+    /// rom addresses to explain the code region search for, if any
+    pub trace: Option<Range<usize>>,
+    /// rom ranges known not to be code, e.g. data blocks, which the code region search skips along with the compressed
+    /// segments found
+    pub exclude: Vec<Range<usize>>,
+}
+
+impl Default for AnalysisOptions {
+    fn default() -> Self {
+        Self {
+            find_code_regions: true,
+            determine_compiler: false,
+            find_compressed: false,
+            magicless_compression: false,
+            search_segments: false,
+            identify_encoders: false,
+            find_dma_tables: false,
+            estimate_function_count: false,
+            ngrams: None,
+            rom_map: false,
+            entropy: None,
+            ipl3_references: Vec::new(),
+            raw: false,
+            start: 0,
+            end: None,
+            trace: None,
+            exclude: Vec::new(),
+        }
+    }
+}
+
+/// Everything found by `analyse_rom`. Analyses that were not requested are `None` (or empty).
+#[derive(Debug)]
+pub struct RomReport {
+    /// `None` if the rom is too small to have a header
+    pub header: Option<RomHeader>,
+    /// `None` if the rom is too small to contain an IPL3
+    pub cic: Option<Ipl3Identification>,
+    /// Header entrypoint corrected for the CIC, i.e. the address the boot segment is actually loaded to
+    pub entrypoint: Option<u32>,
+    /// `None` if the CIC's checksum algorithm is unknown or the rom is too small
+    pub checksum: Option<ChecksumReport>,
+    pub code_regions: Option<Vec<RomRegion>>,
+    pub function_count_estimate: Option<usize>,
+    pub compiler: Option<CompilerReport>,
+    pub compressed_segments: Option<Vec<CompressedSegment>>,
+    /// Code found inside each compressed segment, if `AnalysisOptions::search_segments` was set
+    pub segment_code: Option<Vec<SegmentCode>>,
+    /// How close each encoder strategy gets to each segment, if `AnalysisOptions::identify_encoders` was set. Segments
+    /// in formats that can't be encoded are left out.
+    pub encoders: Option<Vec<SegmentEncoders>>,
+    pub dma_tables: Option<Vec<DmaTable>>,
+    pub ngrams: Vec<NgramTable>,
+    pub rom_map: Option<RomMap>,
+    /// `None` if entropy analysis was not requested
+    pub high_entropy_spans: Option<Vec<EntropySpan>>,
+    /// Layout of the disk, if a 64DD disk image was analysed (see `analyse_disk`)
+    pub disk: Option<DiskLayout>,
+    /// Why code regions start and end where they do in `AnalysisOptions::trace`, in the order the decisions were made
+    pub trace: Vec<TraceEvent>,
+}
+
+impl AnalysisOptions {
+    /// The range of rom addresses searched for code and compressed segments, clamped to the rom
+    pub fn window(&self, rom_len: usize) -> Range<usize> {
+        let end = round_down(self.end.unwrap_or(rom_len).min(rom_len), WORD_SIZE);
+        round_up(self.start, WORD_SIZE).min(end)..end
+    }
+}
+
+impl RomReport {
+    /// The address a rom offset is loaded to, if it is in the boot segment
+    pub fn boot_vram(&self, rom_addr: usize) -> Option<u32> {
+        if let Some(disk) = &self.disk {
+            return disk.boot_vram(rom_addr);
+        }
+        let entrypoint = self.entrypoint?;
+        (IPL3_END..IPL3_END + BOOT_SEGMENT_SIZE)
+            .contains(&rom_addr)
+            .then(|| entrypoint.wrapping_add((rom_addr - IPL3_END) as u32))
+    }
+}
+
+pub fn configure_rabbitizer() {
+    rabbitizer::config_set_treat_j_as_unconditional_branch(true);
+}
+
+/// Convert a rom to big-endian, detecting the byte order from the header unless `byte_order` is given. A raw binary is
+/// assumed to be big-endian already unless told otherwise.
+pub fn load_rom(
+    mut rom_bytes: Vec<u8>,
+    byte_order: Option<Endian>,
+    raw: bool,
+) -> Result<(Vec<u8>, Endian)> {
+    if !raw && rom_bytes.len() < IPL3_END {
+        return Err(AnalyserError::TruncatedRom {
+            size: rom_bytes.len(),
+            expected: IPL3_END,
+        });
+    }
+
+    let endian = match byte_order {
+        Some(endian) => endian,
+        None if raw => Endian::Good,
+        None => get_endian(&rom_bytes)?,
+    };
+    reend_array(&mut rom_bytes, &endian)?;
+
+    Ok((rom_bytes, endian))
+}
+
+/// Analyse a rom that has already been converted to big-endian (see `load_rom`)
+pub fn analyse_rom(rom_bytes: &[u8], options: &AnalysisOptions) -> RomReport {
+    configure_rabbitizer();
+
+    let window = options.window(rom_bytes.len());
+    // A raw binary has no header or IPL3, so code can start anywhere
+    let search_start = window
+        .start
+        .max(if options.raw { 0 } else { IPL3_END })
+        .min(window.end);
+    let window_bytes = &rom_bytes[..window.end];
+
+    let header = (!options.raw)
+        .then(|| RomHeader::parse(rom_bytes).ok())
+        .flatten();
+    let cic = (!options.raw)
+        .then(|| rom_bytes.get(HEADER_SIZE..))
+        .flatten()
+        .and_then(|ipl3| ipl3::identify(ipl3, &options.ipl3_references).ok());
+    let entrypoint = header
+        .as_ref()
+        .zip(cic.as_ref())
+        .map(|(header, cic)| cic.correct_entrypoint(header.entrypoint));
+    let checksum = cic
+        .as_ref()
+        .and_then(Ipl3Identification::boot_checksum)
+        .and_then(|algorithm| checksum::verify(rom_bytes, algorithm));
+
+    let needs_code_regions = options.find_code_regions
+        || options.determine_compiler
+        || options.ngrams.is_some()
+        || options.find_dma_tables
+        || options.rom_map
+        || options.entropy.is_some()
+        || options.trace.is_some();
+
+    // Compressed segments are excluded from the code region search, so are needed first
+    let needs_compressed = needs_code_regions
+        || options.find_compressed
+        || options.search_segments
+        || options.identify_encoders
+        || options.estimate_function_count;
+    let compressed_segments = needs_compressed.then(|| {
+        compression::find_all(window_bytes, window.start, options.magicless_compression)
+    });
+
+    // Skipped by the code region search and the function count estimate alike
+    let excluded = options
+        .exclude
+        .iter()
+        .cloned()
+        .chain(
+            compressed_segments
+                .iter()
+                .flatten()
+                .map(|seg| seg.rom_start()..seg.rom_end()),
+        )
+        .collect::<Vec<_>>();
+
+    let mut trace = options.trace.clone().map_or_else(Trace::disabled, Trace::new);
+    let code_regions = needs_code_regions.then(|| {
+        findcode::find_code_regions_excluding(window_bytes, search_start, &excluded, &mut trace)
+    });
+    let regions = code_regions.as_deref().unwrap_or_default();
+
+    let function_count_estimate = options
+        .estimate_function_count
+        .then(|| findcode::estimate_function_count(window_bytes, search_start, &excluded));
+
+    let compiler = options
+        .determine_compiler
+        .then(|| compiler::analyse(rom_bytes, regions));
+
+    let segment_code = options.search_segments.then(|| {
+        segments::analyse_all(
+            rom_bytes,
+            compressed_segments.as_deref().unwrap_or_default(),
+            options.estimate_function_count,
+            options.determine_compiler,
+        )
+    });
+
+    let encoders = options.identify_encoders.then(|| {
+        compression::encode::identify_all(
+            rom_bytes,
+            compressed_segments.as_deref().unwrap_or_default(),
+        )
+    });
+
+    let dma_tables = options.find_dma_tables.then(|| {
+        dmatable::find_tables(
+            window_bytes,
+            search_start,
+            regions,
+            compressed_segments.as_deref().unwrap_or_default(),
+        )
+    });
+
+    let ngrams = options
+        .ngrams
+        .as_ref()
+        .map(|ngram_options| ngrams::tables(rom_bytes, regions, ngram_options))
+        .unwrap_or_default();
+
+    let rom_map = (options.rom_map || options.entropy.is_some()).then(|| {
+        RomMap::build(
+            rom_bytes,
+            regions,
+            compressed_segments.as_deref().unwrap_or_default(),
+            options.raw,
+        )
+    });
+
+    let high_entropy_spans = options.entropy.as_ref().map(|entropy_options| {
+        let covered = rom_map.as_ref().map(RomMap::explained).unwrap_or_default();
+        entropy::find_high_entropy(window_bytes, search_start, entropy_options, &covered)
+    });
+
+    RomReport {
+        header,
+        cic,
+        entrypoint,
+        checksum,
+        code_regions: code_regions.filter(|_| options.find_code_regions),
+        function_count_estimate,
+        compiler,
+        compressed_segments: compressed_segments.filter(|_| options.find_compressed),
+        segment_code,
+        encoders,
+        dma_tables,
+        ngrams,
+        rom_map: rom_map.filter(|_| options.rom_map),
+        high_entropy_spans,
+        disk: None,
+        trace: trace.into_events(),
+    }
+}
+
+/// Analyse an LBA-ordered 64DD disk image. The system area is skipped, so the search covers the boot and data areas
+/// (clamped to `options.start` and `options.end`), and addresses are offsets into the image.
+pub fn analyse_disk(disk_bytes: &[u8], options: &AnalysisOptions) -> Result<RomReport> {
+    let disk = DiskLayout::parse(disk_bytes)?;
+
+    let options = AnalysisOptions {
+        raw: true,
+        start: options.start.max(disk.boot_area().start),
+        ..options.clone()
+    };
+    let mut report = analyse_rom(disk_bytes, &options);
+    report.disk = Some(disk);
+
+    Ok(report)
+}
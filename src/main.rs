@@ -1,153 +1,1310 @@
-mod compiler;
-mod compression;
-mod findcode;
-mod utils;
-// mod ipl3;
-
-mod ngrams;
-
 use argh::FromArgs;
-use parse_int;
-use std::{
-    fs::{self, File},
-    io::{self, Read},
-    time::Instant,
+use n64rom_analyser::{
+    batch::{self, BatchFormat},
+    checksum,
+    compiler::CompilerReport,
+    compression::{self, encode::SegmentEncoders, CompressedSegment},
+    diff::{self, RomDiff},
+    disk::DiskLayout,
+    dmatable::{DmaTable, TableEntry},
+    entropy::{EntropyOptions, EntropySpan},
+    error::{AnalyserError, Result},
+    extract,
+    findcode::RomRegion,
+    header::{RomHeader, HEADER_SIZE},
+    ipl3::{self, Ipl3Identification, Ipl3Match, ReferenceIpl3},
+    json,
+    ngrams::{self, NgramOptions, NgramTable},
+    rommap::{Category, RomMap},
+    segments::SegmentCode,
+    splat,
+    utils::*,
+    AnalysisOptions, RomReport, BOOT_SEGMENT_SIZE, INSTRUCTION_SIZE, IPL3_END,
 };
-use utils::*;
-
-const INSTRUCTION_SIZE: usize = 4;
-const WORD_SIZE: usize = 4;
-
-const IPL3_END: usize = 0x1000;
+use parse_int;
+use std::{ffi::OsStr, fs, io, ops::Range, path::Path, str::FromStr};
 
 // const MIN_REGION_INSTRUCTIONS: usize = 4;
 const SHOW_TRUE_RANGES: bool = false;
+/// Number of n-grams shown per table unless told otherwise
+const DEFAULT_NGRAM_TOP: usize = 20;
 
-fn parse_number(input: &str) -> Result<usize, String> {
+fn parse_number(input: &str) -> std::result::Result<usize, String> {
     parse_int::parse::<usize>(input).map_err(|_| input.to_string())
 }
 
+/// Parse `start-end`, or a single address for just the instruction there
+fn parse_range(input: &str) -> std::result::Result<Range<usize>, String> {
+    match input.split_once('-') {
+        Some((start, end)) => Ok(parse_number(start)?..parse_number(end)?),
+        None => {
+            let addr = parse_number(input)?;
+            Ok(addr..addr + INSTRUCTION_SIZE)
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    Json,
+    Splat,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "splat" => Ok(OutputFormat::Splat),
+            _ => Err(format!("unknown format `{}`, expected text, json or splat", s)),
+        }
+    }
+}
+
 #[derive(FromArgs)]
-/// Analyse a Nintendo 64 rom.
-pub struct Args {
-    /// romfile to read
-    #[argh(positional)]
-    rom: String,
+/// Analyse a Nintendo 64 rom. The options before the subcommand are shared, and used by every subcommand they apply to.
+struct Args {
+    /// byte order of the input (z64, n64 or v64) instead of detecting it from the header
+    #[argh(option)]
+    byte_order: Option<Endian>,
+
+    /// analyse a bare big-endian binary with no header or IPL3, e.g. an extracted overlay
+    #[argh(switch)]
+    raw: bool,
+
+    /// analyse a 64DD disk image in LBA order; assumed for `.ndd` files
+    #[argh(switch)]
+    disk: bool,
+
+    /// directory of reference IPL3 dumps named after their CIC (e.g. `5101.bin`), used to identify IPL3s without a known CRC
+    #[argh(option)]
+    ipl3_dir: Option<String>,
+
+    /// start of search, expect hex. Reported addresses are still relative to the start of the rom
+    #[argh(option, from_str_fn(parse_number))]
+    start: Option<usize>,
 
-    // Could implement `start`, but fiddlier and less useful.
     /// end of search, expect hex
     #[argh(option, from_str_fn(parse_number))]
     end: Option<usize>,
 
-    /// attempt to determine compiler
-    #[argh(switch, short = 'c')]
-    determine_compiler: bool,
+    /// skip a range known not to be code when searching for code regions, e.g. `0x10000-0x18000`; can be repeated. Compressed segments are always skipped
+    #[argh(option, from_str_fn(parse_range))]
+    exclude: Vec<Range<usize>>,
 
-    /// find compressed segments
-    #[argh(switch, short = 'y')]
-    find_compressed: bool,
+    /// also search for compression formats with no magic number (Yay0 variants, Hudson LZSS, Konami LZKN64), which are far more prone to false positives
+    #[argh(switch)]
+    magicless: bool,
 
-    /// prints number of `jr $ra` instructions found (currently limited to uncompressed segments). This is a crude estimate of the number of functions; it will overestimate if an early return is present.
+    #[argh(subcommand)]
+    command: Command,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum Command {
+    Info(InfoArgs),
+    Regions(RegionsArgs),
+    Compiler(CompilerArgs),
+    Compression(CompressionArgs),
+    Ngrams(NgramsArgs),
+    Map(MapArgs),
+    Entropy(EntropyArgs),
+    Disasm(DisasmArgs),
+    Report(ReportArgs),
+    Batch(BatchArgs),
+    Diff(DiffArgs),
+    FixCrc(FixCrcArgs),
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "info")]
+/// Print the header, CIC and checksum.
+struct InfoArgs {
+    /// romfile to read
+    #[argh(positional)]
+    rom: String,
+
+    /// output format: text (default) or json
+    #[argh(option, default = "OutputFormat::Text")]
+    format: OutputFormat,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "regions")]
+/// Find code regions.
+struct RegionsArgs {
+    /// romfile to read
+    #[argh(positional)]
+    rom: String,
+
+    /// output format: text (default), json, or splat for a skeleton splat YAML config
+    #[argh(option, default = "OutputFormat::Text")]
+    format: OutputFormat,
+
+    /// prints number of `jr $ra` instructions found (inside compressed segments too with --segments). This is a crude estimate of the number of functions; it will overestimate if an early return is present.
     #[argh(switch, short = 'f')]
     estimate_function_count: bool,
+
+    /// also look for code inside decompressed segments, reported as `segment+offset`
+    #[argh(switch)]
+    segments: bool,
+
+    /// explain why code regions start and end where they do in a range of rom addresses, e.g. `0x1000-0x2000`
+    #[argh(option, from_str_fn(parse_range))]
+    trace: Option<Range<usize>>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "compiler")]
+/// Attempt to determine the compiler of each code region.
+struct CompilerArgs {
+    /// romfile to read
+    #[argh(positional)]
+    rom: String,
+
+    /// output format: text (default) or json
+    #[argh(option, default = "OutputFormat::Text")]
+    format: OutputFormat,
+
+    /// also look for code inside decompressed segments, reported as `segment+offset`
+    #[argh(switch)]
+    segments: bool,
 }
 
-fn configure_rabbitizer() {
-    rabbitizer::config_set_treat_j_as_unconditional_branch(true);
+#[derive(FromArgs)]
+#[argh(subcommand, name = "compression")]
+/// Find compressed segments.
+struct CompressionArgs {
+    /// romfile to read
+    #[argh(positional)]
+    rom: String,
+
+    /// output format: text (default) or json
+    #[argh(option, default = "OutputFormat::Text")]
+    format: OutputFormat,
+
+    /// directory to write each segment to, raw and decompressed, along with a manifest of their offsets, sizes, CRC-32s and SHA-1s
+    #[argh(option)]
+    extract: Option<String>,
+
+    /// re-encode each Yaz0, Yay0 and MIO0 segment with Nintendo's, a greedy and an optimal encoder, and report which reproduces it exactly or how close each gets
+    #[argh(switch)]
+    encoders: bool,
+
+    /// find file tables (e.g. Zelda's dmadata) of start/end offset pairs that land on the compressed segments and code regions, which give the real file boundaries
+    #[argh(switch)]
+    tables: bool,
 }
 
-fn read_rom(args: &Args) -> io::Result<Vec<u8>> {
-    let mut rom_bytes = Vec::with_capacity(0x100000);
+#[derive(FromArgs)]
+#[argh(subcommand, name = "map")]
+/// Classify every 16-byte block of the rom and summarise how much of it is explained.
+struct MapArgs {
+    /// romfile to read
+    #[argh(positional)]
+    rom: String,
 
-    let f = File::open(&args.rom)?;
+    /// output format: text (default) or json
+    #[argh(option, default = "OutputFormat::Text")]
+    format: OutputFormat,
+}
 
-    if let Some(end) = args.end {
-        let mut handle = f.take(end as u64);
-        handle.read(&mut rom_bytes)?;
-        println!("Examining range {:#08X}-{:#08X}", 0, end);
-    } else {
-        rom_bytes = fs::read(&args.rom)?;
-        println!(
-            "Examining full rom, range {:#08X}-{:#08X}",
-            0,
-            rom_bytes.len()
-        );
+#[derive(FromArgs)]
+#[argh(subcommand, name = "entropy")]
+/// Find high-entropy spans that aren't code or known compressed segments, e.g. data in an unknown compression format.
+struct EntropyArgs {
+    /// romfile to read
+    #[argh(positional)]
+    rom: String,
+
+    /// output format: text (default) or json
+    #[argh(option, default = "OutputFormat::Text")]
+    format: OutputFormat,
+
+    /// size of the window the entropy is measured over, expect hex (default 0x1000)
+    #[argh(option, from_str_fn(parse_number))]
+    window: Option<usize>,
+
+    /// distance the window moves each time, expect hex (default 0x400)
+    #[argh(option, from_str_fn(parse_number))]
+    step: Option<usize>,
+
+    /// entropy in bits per byte at or above which a window counts as high-entropy (default 7.5)
+    #[argh(option)]
+    threshold: Option<f64>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "ngrams")]
+/// Summarise instruction n-grams over the code regions.
+struct NgramsArgs {
+    /// romfile to read
+    #[argh(positional)]
+    rom: String,
+
+    /// output format: text (default) or json
+    #[argh(option, default = "OutputFormat::Text")]
+    format: OutputFormat,
+
+    /// smallest n-gram size to summarise (default 1)
+    #[argh(option, default = "1")]
+    min_n: usize,
+
+    /// largest n-gram size to summarise (default 4)
+    #[argh(option, default = "4")]
+    max_n: usize,
+
+    /// keep only this many of the most frequent n-grams in each table (default 20, unless --min-count is given)
+    #[argh(option)]
+    top: Option<usize>,
+
+    /// keep only n-grams seen at least this many times
+    #[argh(option)]
+    min_count: Option<usize>,
+
+    /// one table per code region instead of one for the whole rom
+    #[argh(switch)]
+    per_region: bool,
+
+    /// show each frequency as a percentage of all the n-grams in the table as well
+    #[argh(switch)]
+    relative: bool,
+
+    /// show the disassembly of the first occurrence of each n-gram
+    #[argh(switch)]
+    disasm: bool,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "disasm")]
+/// Disassemble a range of the rom.
+struct DisasmArgs {
+    /// romfile to read
+    #[argh(positional)]
+    rom: String,
+
+    /// start of the range, expect hex
+    #[argh(option, from_str_fn(parse_number))]
+    start: usize,
+
+    /// end of the range, expect hex (default 0x100 bytes after the start)
+    #[argh(option, from_str_fn(parse_number))]
+    end: Option<usize>,
+
+    /// disassemble as RSP microcode
+    #[argh(switch)]
+    rsp: bool,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "report")]
+/// Run every analysis.
+struct ReportArgs {
+    /// romfile to read
+    #[argh(positional)]
+    rom: String,
+
+    /// output format: text (default), json, or splat for a skeleton splat YAML config
+    #[argh(option, default = "OutputFormat::Text")]
+    format: OutputFormat,
+
+    /// explain why code regions start and end where they do in a range of rom addresses, e.g. `0x1000-0x2000`
+    #[argh(option, from_str_fn(parse_range))]
+    trace: Option<Range<usize>>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "batch")]
+/// Analyse every .z64/.n64/.v64 in a directory in parallel and print one summary table.
+struct BatchArgs {
+    /// directory of roms to read
+    #[argh(positional)]
+    dir: String,
+
+    /// output format: csv (default) or json
+    #[argh(option, default = "BatchFormat::Csv")]
+    format: BatchFormat,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "diff")]
+/// Run every analysis on two roms, e.g. two revisions of a game, and compare their code regions and compressed segments.
+struct DiffArgs {
+    /// first romfile to read
+    #[argh(positional)]
+    a: String,
+
+    /// second romfile to read
+    #[argh(positional)]
+    b: String,
+
+    /// output format: text (default) or json
+    #[argh(option, default = "OutputFormat::Text")]
+    format: OutputFormat,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "fix-crc")]
+/// Write a copy of the rom with corrected CRC1/CRC2, in the same byte order as the input.
+struct FixCrcArgs {
+    /// romfile to read
+    #[argh(positional)]
+    rom: String,
+
+    /// path to write the fixed rom to
+    #[argh(positional)]
+    output: String,
+}
+
+/// One rom to read, with the shared options for how to read and search it
+struct Input<'a> {
+    rom: &'a str,
+    shared: &'a Args,
+}
+
+impl Args {
+    fn input<'a>(&'a self, rom: &'a str) -> Input<'a> {
+        Input { rom, shared: self }
+    }
+}
+
+impl Input<'_> {
+    fn is_disk(&self) -> bool {
+        self.shared.disk || Path::new(self.rom).extension() == Some(OsStr::new("ndd"))
+    }
+
+    /// Options that select nothing but the window, the excluded ranges and the IPL3 references; each subcommand adds
+    /// what it needs
+    fn analysis_options(&self) -> Result<AnalysisOptions> {
+        let shared = self.shared;
+        Ok(AnalysisOptions {
+            find_code_regions: false,
+            magicless_compression: shared.magicless,
+            ipl3_references: load_references(shared.ipl3_dir.as_deref())?,
+            raw: shared.raw,
+            start: shared.start.unwrap_or(0),
+            end: shared.end,
+            exclude: shared.exclude.clone(),
+            ..Default::default()
+        })
+    }
+
+    fn read_rom(&self) -> Result<(Vec<u8>, Endian)> {
+        let rom_bytes = fs::read(self.rom)?;
+        n64rom_analyser::load_rom(rom_bytes, self.shared.byte_order, self.shared.raw)
     }
 
-    let endian = get_endian(&rom_bytes)?;
-    reend_array(&mut rom_bytes, &endian);
+    /// Read the rom or disk image and run the selected analyses, returning it big-endian along with the report
+    fn analyse(&self, options: &AnalysisOptions) -> Result<(Vec<u8>, RomReport)> {
+        if self.is_disk() {
+            let disk_bytes = fs::read(self.rom)?;
+            let report = n64rom_analyser::analyse_disk(&disk_bytes, options)?;
+            Ok((disk_bytes, report))
+        } else {
+            let (rom_bytes, _) = self.read_rom()?;
+            let report = n64rom_analyser::analyse_rom(&rom_bytes, options);
+            Ok((rom_bytes, report))
+        }
+    }
+}
 
-    Ok(rom_bytes)
+fn load_references(ipl3_dir: Option<&str>) -> Result<Vec<ReferenceIpl3>> {
+    match ipl3_dir {
+        Some(dir) => ipl3::load_references(Path::new(dir)),
+        None => Ok(Vec::new()),
+    }
 }
 
-fn run(args: Args) -> io::Result<()> {
-    let rom_bytes = read_rom(&args)?;
+fn fix_crc(
+    mut rom_bytes: Vec<u8>,
+    endian: Endian,
+    path: &str,
+    references: &[ReferenceIpl3],
+) -> Result<()> {
+    let algorithm = ipl3::identify(rom_bytes.get(HEADER_SIZE..).unwrap_or_default(), references)?
+        .boot_checksum()
+        .ok_or(AnalyserError::UnknownCic)?;
 
-    let code_regions = findcode::find_code_regions(&args, &rom_bytes);
+    let (crc1, crc2) =
+        checksum::fix(&mut rom_bytes, algorithm).ok_or(AnalyserError::TruncatedRom {
+            size: rom_bytes.len(),
+            expected: IPL3_END + BOOT_SEGMENT_SIZE,
+        })?;
+
+    // Re-ending is its own inverse
+    reend_array(&mut rom_bytes, &endian)?;
+    fs::write(path, rom_bytes)?;
+    println!("Wrote {} with CRC1/CRC2 0x{:08X} 0x{:08X}", path, crc1, crc2);
+
+    Ok(())
+}
+
+fn print_header(header: &RomHeader) {
+    println!("Header:");
+    println!("  Name:             {}", header.name);
+    println!(
+        "  Game code:        {} ({}, {})",
+        header.full_game_code(),
+        header.category().unwrap_or("unknown category"),
+        header.country().unwrap_or("unknown country")
+    );
+    println!("  Revision:         {}", header.revision);
+    println!("  Entrypoint:       0x{:08X}", header.entrypoint);
+    println!(
+        "  libultra release: 0x{:08X} ({})",
+        header.libultra_release,
+        header.libultra_version().as_deref().unwrap_or("unknown")
+    );
+    println!("  CRC1/CRC2:        0x{:08X} 0x{:08X}", header.crc1, header.crc2);
+    println!("  Clock rate:       0x{:08X}", header.clock_rate);
+    println!("  PI BSD DOM1:      {}", header.pi_bsd_dom1_config);
+
+    for warning in header.warnings() {
+        println!("  Warn: {}", warning);
+    }
+}
+
+fn print_cic(report: &RomReport, identification: &Ipl3Identification) {
+    print!("CIC: ");
+    match identification.cic {
+        Some(cic) => {
+            print!("{}", cic.name());
+            if let Some(header) = &report.header {
+                print!(" ({} for this rom)", cic.regional_name(header.is_pal()));
+            }
+            print!(", {:?}", cic.platform());
+        }
+        None => print!("unk"),
+    }
+    match identification.method {
+        Ipl3Match::Crc => (),
+        Ipl3Match::Signature => print!(", matched by signature"),
+        Ipl3Match::Reference => print!(", matched reference dump"),
+        Ipl3Match::Nearest(similarity) => {
+            print!(", nearest known IPL3 ({:.1}% similar)", similarity * 100.0)
+        }
+        Ipl3Match::Unknown => (),
+    }
+    println!();
+    println!(
+        "IPL3 CRC-32/CKSUM 0x{:08X}, CRC-32 0x{:08X}",
+        identification.cksum, identification.crc32
+    );
+
+    if let (Some(header), Some(entrypoint)) = (&report.header, report.entrypoint) {
+        if entrypoint != header.entrypoint {
+            println!(
+                "Boot address: 0x{:08X} (header entrypoint 0x{:08X} corrected for CIC)",
+                entrypoint, header.entrypoint
+            );
+        } else {
+            println!("Boot address: 0x{:08X}", entrypoint);
+        }
+    }
+
+    match &report.checksum {
+        Some(checksum) => println!(
+            "Checksum: header 0x{:08X} 0x{:08X}, calculated 0x{:08X} 0x{:08X}: {}",
+            checksum.header.0,
+            checksum.header.1,
+            checksum.calculated.0,
+            checksum.calculated.1,
+            if checksum.matches() { "match" } else { "MISMATCH" }
+        ),
+        None => println!("Checksum: cannot be verified"),
+    }
+}
+
+/// An address in the input, as LBA + offset for a disk image
+fn format_address(disk: Option<&DiskLayout>, addr: usize, width: usize) -> String {
+    match disk {
+        Some(disk) => disk.format_offset(addr),
+        None => format!("{:width$X}", addr, width = width),
+    }
+}
+
+fn print_disk(disk: &DiskLayout) {
+    let system = &disk.system;
+    println!(
+        "Disk region: {} (0x{:08X}){}",
+        system.region_name(),
+        system.region,
+        if system.development {
+            ", development system area"
+        } else {
+            ""
+        }
+    );
+    println!("Disk type: {}", system.disk_type);
+    println!(
+        "IPL: {} blocks loaded to 0x{:08X}",
+        system.ipl_load_blocks, system.ipl_load_address
+    );
+    // Results in either area are reported by LBA, so these say which one they are in
+    let boot_area = disk.boot_area();
+    println!(
+        "Boot area: [{}, {})",
+        disk.format_offset(boot_area.start),
+        disk.format_offset(boot_area.end)
+    );
+    let data_area = disk.data_area();
+    println!(
+        "Data area: [{}, {})",
+        disk.format_offset(data_area.start),
+        disk.format_offset(data_area.end)
+    );
+    println!(
+        "ROM area: LBAs 0-{}, RAM area: LBAs {}-{}",
+        disk.rom_end_lba() - 1,
+        disk.rom_end_lba(),
+        disk.lba_count() - 1
+    );
+}
+
+fn print_code_regions(report: &RomReport, code_regions: &[RomRegion]) {
+    let disk = report.disk.as_ref();
     println!(
         "Found {} code region{}:",
         code_regions.len(),
         if code_regions.len() > 1 { "s" } else { "" }
     );
 
-    for codeseg in &code_regions {
+    for codeseg in code_regions {
         let start = round_down(codeseg.rom_start(), 0x10);
         let end = round_up(codeseg.rom_end(), 0x10);
 
         if !SHOW_TRUE_RANGES {
-            println!(
-                "  [{:08X}, {:08X}) (size 0x{:06X}) rsp: {}",
-                start,
-                end,
+            print!(
+                "  [{}, {}) (size 0x{:06X}) rsp: {}",
+                format_address(disk, start, 8),
+                format_address(disk, end, 8),
                 end - start,
                 codeseg.has_rsp()
             );
         } else {
-            println!(
-                "  [{:08X}, {:08X}) (size 0x{:06X}) rsp: {}",
-                codeseg.rom_start(),
-                codeseg.rom_end(),
+            print!(
+                "  [{}, {}) (size 0x{:06X}) rsp: {}",
+                format_address(disk, codeseg.rom_start(), 8),
+                format_address(disk, codeseg.rom_end(), 8),
                 codeseg.rom_end() - codeseg.rom_start(),
                 codeseg.has_rsp()
             );
-            if codeseg.rom_start() != start {
-                print!("    Warn: code region doesn't start at 16 byte alignment");
+        }
+        if let Some(vram) = report.boot_vram(codeseg.rom_start()) {
+            print!(" vram: 0x{:08X}", vram);
+        }
+        println!();
+        if SHOW_TRUE_RANGES && codeseg.rom_start() != start {
+            print!("    Warn: code region doesn't start at 16 byte alignment");
+        }
+    }
+}
+
+fn print_compiler(report: &CompilerReport, address: impl Fn(usize) -> String) {
+    for region in &report.regions {
+        let evidence = &region.evidence;
+        print!("[{}, {}):  ", address(region.rom_start), address(region.rom_end));
+        print!("b: {:4}, j: {:4}  ", evidence.b_count, evidence.j_count);
+        print!(
+            "lui-(ori)-mtc1: {:4}, isolated mtc1: {:4}  ",
+            evidence.float_load_pattern_count, evidence.isolated_mtc1_count
+        );
+        print!(
+            "break 6: pattern: {:2}, other: {:2}, break 7: pattern: {:2}, other: {:2}  ",
+            evidence.break_6_pattern_count,
+            evidence.other_break_6_count,
+            evidence.break_7_pattern_count,
+            evidence.other_break_7_count,
+        );
+
+        print!("Possible compilers: ");
+        let mut compiler_list = region
+            .possible_compilers
+            .iter()
+            .map(|v| format!("{:?}", v))
+            .collect::<Vec<_>>();
+        compiler_list.sort();
+        println!("{{{}}}", compiler_list.join(","));
+    }
+
+    let total = &report.total;
+    println!();
+    println!("Total b/j: b: {:4}, j: {:4}", total.b_count, total.j_count);
+    println!(
+        "Total mtc1: lui-(ori)-mtc1: {:4}, isolated mtc1: {:4}",
+        total.float_load_pattern_count, total.isolated_mtc1_count
+    );
+    println!(
+        "Total break 6: pattern {:2}, other {:2}, break 7: pattern {:2}, other {:2} ",
+        total.break_6_pattern_count,
+        total.other_break_6_count,
+        total.break_7_pattern_count,
+        total.other_break_7_count,
+    );
+
+    println!();
+    println!("Possible: ");
+    let mut compiler_list = report
+        .possible_compilers
+        .iter()
+        .map(|v| format!("{}", v))
+        .collect::<Vec<_>>();
+    compiler_list.sort();
+    print!("{}", compiler_list.join(", "));
+    println!();
+}
+
+fn print_rom_map(map: &RomMap, disk: Option<&DiskLayout>) {
+    for run in &map.runs {
+        println!(
+            "  [{}, {}) (size 0x{:06X}) {}",
+            format_address(disk, run.rom_start, 8),
+            format_address(disk, run.rom_end, 8),
+            run.size(),
+            run.category
+        );
+    }
+
+    println!();
+    println!("Coverage:");
+    for category in Category::ALL {
+        println!(
+            "  {:10} 0x{:08X} {:6.2}%",
+            category.to_string(),
+            map.bytes(category),
+            100.0 * map.coverage(category)
+        );
+    }
+}
+
+fn print_entropy(spans: &[EntropySpan], disk: Option<&DiskLayout>) {
+    println!("Found {} high-entropy span{}:", spans.len(), if spans.len() == 1 { "" } else { "s" });
+    for span in spans {
+        println!(
+            "  [{}, {}) (size 0x{:06X}) {:.2} bits/byte",
+            format_address(disk, span.rom_start, 8),
+            format_address(disk, span.rom_end, 8),
+            span.rom_end - span.rom_start,
+            span.entropy
+        );
+    }
+}
+
+fn print_compressed(segments: &[CompressedSegment], disk: Option<&DiskLayout>) {
+    for &algorithm in compression::Type::ALL {
+        let found = segments
+            .iter()
+            .filter(|seg| seg.algorithm() == algorithm)
+            .collect::<Vec<_>>();
+
+        if !found.is_empty() {
+            println!(
+                "{} {} segments found",
+                found.len(),
+                algorithm
+            );
+            for seg in found {
+                println!(
+                    "  [{}, {}) 0x{:06X} -> 0x{:06X} ({:.1}%)",
+                    format_address(disk, seg.rom_start(), 8),
+                    format_address(disk, seg.rom_end(), 8),
+                    seg.compressed_size(),
+                    seg.decompressed_size(),
+                    100.0 * seg.ratio()
+                );
             }
         }
     }
+}
 
-    if args.determine_compiler {
+/// Which encoder strategies reproduce each segment, and how far into it the others get
+fn print_encoders(segments: &[SegmentEncoders], disk: Option<&DiskLayout>) {
+    for encoders in segments {
+        let segment = &encoders.segment;
+        let exact = encoders.exact().map(|strategy| strategy.name()).collect::<Vec<_>>();
+        println!(
+            "{} segment at {} (0x{:06X} compressed): {}",
+            segment.algorithm(),
+            format_address(disk, segment.rom_start(), 0),
+            segment.compressed_size(),
+            if exact.is_empty() {
+                "no exact match".to_string()
+            } else {
+                format!("matches {}", exact.join(", "))
+            }
+        );
+        for encoder in &encoders.matches {
+            println!(
+                "  {:<8} 0x{:06X} bytes, {:.1}% before the first difference",
+                encoder.strategy.name(),
+                encoder.size,
+                100.0 * encoder.matching_prefix as f64 / segment.compressed_size() as f64
+            );
+        }
+    }
+}
+
+/// Each file table's layout, then the file boundaries it lists
+fn print_dma_tables(tables: &[DmaTable], disk: Option<&DiskLayout>) {
+    for table in tables {
+        println!(
+            "[{}, {}) {} entr{} of 0x{:X} bytes, offsets at +0x{:X}, {} on known boundaries",
+            format_address(disk, table.rom_start, 8),
+            format_address(disk, table.rom_end, 8),
+            table.entries.len(),
+            if table.entries.len() == 1 { "y" } else { "ies" },
+            table.layout.entry_size,
+            table.layout.start_offset,
+            table.hits
+        );
+        for (i, entry) in table.entries.iter().enumerate() {
+            match *entry {
+                TableEntry::File { start, end } => println!(
+                    "  {:4}: [{}, {})",
+                    i,
+                    format_address(disk, start, 8),
+                    format_address(disk, end, 8)
+                ),
+                TableEntry::StartOnly { start } => {
+                    println!("  {:4}: [{}, ?)", i, format_address(disk, start, 8))
+                }
+                TableEntry::Absent => println!("  {:4}: absent", i),
+            }
+        }
+    }
+}
+
+/// Code found in each decompressed segment, with addresses as `segment+offset`
+fn print_segment_code(segments: &[SegmentCode], disk: Option<&DiskLayout>) {
+    for code in segments {
+        let segment = &code.segment;
+        let address = |offset: usize| {
+            format!("{}+{:X}", format_address(disk, segment.rom_start(), 0), offset)
+        };
+
+        println!(
+            "{} segment at {} (0x{:06X} decompressed): {} code region{}",
+            segment.algorithm(),
+            format_address(disk, segment.rom_start(), 0),
+            segment.decompressed_size(),
+            code.code_regions.len(),
+            if code.code_regions.len() == 1 { "" } else { "s" }
+        );
+        if let Some(function_count) = code.function_count_estimate {
+            println!("  Estimated function count: {}", function_count);
+        }
+        for region in &code.code_regions {
+            let start = round_down(region.rom_start(), 0x10);
+            let end = round_up(region.rom_end(), 0x10);
+            println!(
+                "  [{}, {}) (size 0x{:06X}) rsp: {}",
+                address(start),
+                address(end),
+                end - start,
+                region.has_rsp()
+            );
+        }
+        if let Some(compiler) = &code.compiler {
+            println!();
+            print_compiler(compiler, address);
+            println!();
+        }
+    }
+}
+
+fn print_ngrams(table: &NgramTable, rom_bytes: &[u8], text_options: TextOptions) {
+    match &table.region {
+        Some(region) => println!(
+            "{}-grams for region [{:08X}, {:08X}), {} instructions",
+            table.n,
+            region.rom_start(),
+            region.rom_end(),
+            table.instruction_count
+        ),
+        None => println!(
+            "{}-grams for {} regions, {} instructions",
+            table.n, table.region_count, table.instruction_count
+        ),
+    }
+
+    for entry in &table.entries {
+        print!("  {:6}", entry.count);
+        if text_options.relative_frequencies {
+            print!(" {:6.2}%", table.relative_frequency(entry) * 100.0);
+        }
+        let mnemonics = entry
+            .instructions
+            .iter()
+            .map(ngrams::mnemonic)
+            .collect::<Vec<_>>();
+        println!("  {}", mnemonics.join("; "));
+
+        if text_options.disassemble_ngrams {
+            for (i, line) in entry.disassemble(rom_bytes).iter().enumerate() {
+                println!(
+                    "      {:08X}: {}",
+                    entry.first_rom_addr + i * INSTRUCTION_SIZE,
+                    line
+                );
+            }
+        }
+    }
+}
+
+fn print_report(report: &RomReport, rom_bytes: &[u8], text_options: TextOptions) {
+    if text_options.identification {
+        if let Some(disk) = &report.disk {
+            print_disk(disk);
+            println!();
+        }
+
+        if let Some(header) = &report.header {
+            print_header(header);
+            println!();
+        }
+
+        if let Some(cic) = &report.cic {
+            print_cic(report, cic);
+            println!();
+        }
+    }
+
+    if let Some(function_count) = report.function_count_estimate {
+        println!();
+        println!("Estimated function count: {}", function_count);
+    }
+
+    if let Some(code_regions) = &report.code_regions {
+        print_code_regions(report, code_regions);
+    }
+
+    if !report.trace.is_empty() {
+        println!();
+        println!("Trace:");
+        for event in &report.trace {
+            println!("    {}", event);
+        }
+    }
+
+    if let Some(compiler) = &report.compiler {
         println!();
         println!("Compiler:");
-        compiler::analyse(&args, &rom_bytes, &code_regions)?;
+        print_compiler(compiler, |addr| format_address(report.disk.as_ref(), addr, 7));
     }
 
-    if args.find_compressed {
+    if let Some(segments) = &report.compressed_segments {
         println!();
         println!("Compression:");
-        compression::find_all(&rom_bytes);
+        print_compressed(segments, report.disk.as_ref());
+    }
+
+    if let Some(segments) = &report.segment_code {
+        println!();
+        println!("Code in compressed segments:");
+        print_segment_code(segments, report.disk.as_ref());
+    }
+
+    if let Some(segments) = &report.encoders {
+        println!();
+        println!("Encoders:");
+        print_encoders(segments, report.disk.as_ref());
+    }
+
+    if let Some(tables) = &report.dma_tables {
+        println!();
+        println!("File tables:");
+        print_dma_tables(tables, report.disk.as_ref());
+    }
+
+    if !report.ngrams.is_empty() {
+        println!();
+        println!("Ngrams");
+    }
+
+    for table in &report.ngrams {
+        println!();
+        print_ngrams(table, rom_bytes, text_options);
+    }
+
+    if let Some(spans) = &report.high_entropy_spans {
+        println!();
+        println!("Entropy:");
+        print_entropy(spans, report.disk.as_ref());
+    }
+
+    if let Some(map) = &report.rom_map {
+        println!();
+        println!("Rom map:");
+        print_rom_map(map, report.disk.as_ref());
+    }
+}
+
+/// What the text output includes beyond whatever was analysed, and how it is shown
+#[derive(Clone, Copy)]
+struct TextOptions {
+    identification: bool,
+    window: bool,
+    relative_frequencies: bool,
+    disassemble_ngrams: bool,
+}
+
+impl TextOptions {
+    const IDENTIFICATION: TextOptions = TextOptions {
+        identification: true,
+        window: false,
+        relative_frequencies: false,
+        disassemble_ngrams: false,
+    };
+    const ANALYSIS: TextOptions = TextOptions {
+        identification: false,
+        window: true,
+        relative_frequencies: false,
+        disassemble_ngrams: false,
+    };
+    const ALL: TextOptions = TextOptions {
+        identification: true,
+        window: true,
+        relative_frequencies: false,
+        disassemble_ngrams: false,
+    };
+}
+
+fn print_window(options: &AnalysisOptions, rom_len: usize) {
+    let window = options.window(rom_len);
+    if window == (0..rom_len) {
+        println!(
+            "Examining full rom, range {:#08X}-{:#08X}",
+            window.start, window.end
+        );
+    } else {
+        println!("Examining range {:#08X}-{:#08X}", window.start, window.end);
     }
+}
 
-    let ngram_regions = &code_regions;
+/// Load the input and run the selected analyses, then output the report
+fn analyse(
+    input: &Input,
+    options: &AnalysisOptions,
+    format: OutputFormat,
+    text_options: TextOptions,
+) -> Result<()> {
+    analyse_input(input, options, format, text_options)?;
+    Ok(())
+}
+
+/// `analyse`, returning the big-endian input and the report for a subcommand that does more with them afterwards
+fn analyse_input(
+    input: &Input,
+    options: &AnalysisOptions,
+    format: OutputFormat,
+    text_options: TextOptions,
+) -> Result<(Vec<u8>, RomReport)> {
+    let (rom_bytes, report) = input.analyse(options)?;
+    // Other formats are only the report itself, so they can be parsed
+    if format == OutputFormat::Text && text_options.window && report.disk.is_none() {
+        print_window(options, rom_bytes.len());
+    }
+
+    match format {
+        OutputFormat::Text => print_report(&report, &rom_bytes, text_options),
+        OutputFormat::Json => {
+            json::write_report(&report, io::stdout().lock())?;
+            println!();
+        }
+        OutputFormat::Splat => {
+            if report.disk.is_some() || report.code_regions.is_none() {
+                return Err(AnalyserError::UnsupportedOutput(
+                    "splat configs can only be generated for cartridge roms by the regions and report subcommands",
+                ));
+            }
+            splat::write_config(&report, &rom_bytes, input.rom, io::stdout().lock())?;
+        }
+    }
+    Ok((rom_bytes, report))
+}
+
+fn disasm(input: &Input, args: &DisasmArgs) -> Result<()> {
+    // Only the header and CIC are needed, to know where the boot segment is loaded
+    let (rom_bytes, report) = input.analyse(&input.analysis_options()?)?;
+    let disk = report.disk.as_ref();
+
+    n64rom_analyser::configure_rabbitizer();
+    let end = args.end.unwrap_or(args.start + 0x100).min(rom_bytes.len());
+    for addr in (round_up(args.start, INSTRUCTION_SIZE)..end).step_by(INSTRUCTION_SIZE) {
+        if addr + INSTRUCTION_SIZE > end {
+            break;
+        }
+        let word = read_be_word(&rom_bytes[addr..]);
+        let vram = report.boot_vram(addr);
+        let instruction = if args.rsp {
+            rabbitizer::Instruction::new_rsp(word, vram.unwrap_or(0))
+        } else {
+            rabbitizer::Instruction::new(word, vram.unwrap_or(0))
+        };
+        match vram {
+            Some(vram) => print!("{} {:08X}", format_address(disk, addr, 8), vram),
+            None => print!("{} {:8}", format_address(disk, addr, 8), ""),
+        }
+        println!("  {:08X}  {}", word, instruction.disassemble(None, 0));
+    }
+
+    Ok(())
+}
+
+/// Title, game code and CIC of one side of a diff
+fn print_diff_side(label: &str, path: &str, report: &RomReport) {
+    print!("{}: {}", label, path);
+    if let Some(header) = &report.header {
+        print!(" \"{}\" {} rev {}", header.name, header.full_game_code(), header.revision);
+    }
+    if let Some(cic) = report.cic.as_ref().and_then(|cic| cic.cic) {
+        print!(", CIC {}", cic.name());
+    }
+    println!(
+        ", {} code regions",
+        report.code_regions.as_ref().map_or(0, Vec::len)
+    );
+}
+
+fn print_diff(diff: &RomDiff, a_path: &str, b_path: &str) {
+    print_diff_side("A", a_path, &diff.a);
+    print_diff_side("B", b_path, &diff.b);
+
+    let range = |region: Option<&RomRegion>| {
+        region.map_or_else(
+            || format!("{:18}", ""),
+            |region| format!("[{:6X}, {:6X})", region.rom_start(), region.rom_end()),
+        )
+    };
 
     println!();
-    println!("Ngrams");
+    println!("Code regions:");
+    for region in &diff.regions {
+        print!(
+            "  {} -> {}",
+            range(region.a.as_ref()),
+            range(region.b.as_ref())
+        );
+        match region.similarity {
+            Some(similarity) => print!(" {:6.2}%", 100.0 * similarity),
+            None => print!(" {:7}", ""),
+        }
+        print!("  {}", region.changes().join(", "));
+        match region.size_change() {
+            Some(change) if change > 0 => print!(" (+0x{:X})", change),
+            Some(change) if change < 0 => print!(" (-0x{:X})", -change),
+            _ => {}
+        }
+        println!();
+    }
+
+    let position = |addr: Option<usize>, size: Option<usize>| match (addr, size) {
+        (Some(addr), Some(size)) => format!("{:6X} (0x{:06X})", addr, size),
+        (Some(addr), None) => format!("{:6X} {:10}", addr, ""),
+        _ => format!("{:17}", ""),
+    };
 
-    let now = Instant::now();
-    for n in 1..=4 {
+    if !diff.compressed_segments.is_empty() {
         println!();
-        ngrams::print_summary(&rom_bytes, ngram_regions, n);
-        eprintln!("{n}: {}", now.elapsed().as_millis());
+        println!("Compressed segments (decompressed size):");
     }
+    for seg in &diff.compressed_segments {
+        println!(
+            "  {} {} -> {}  {}",
+            seg.algorithm,
+            position(seg.a, seg.a_size),
+            position(seg.b, seg.b_size),
+            seg.changes().join(", ")
+        );
+    }
+}
+
+fn diff(shared: &Args, args: &DiffArgs) -> Result<()> {
+    if args.format == OutputFormat::Splat {
+        return Err(AnalyserError::UnsupportedOutput("diff can only be output as text or json"));
+    }
+
+    let (a, b) = (shared.input(&args.a), shared.input(&args.b));
+    let options = full_analysis(a.analysis_options()?);
+    let (a_bytes, a) = a.analyse(&options)?;
+    let (b_bytes, b) = b.analyse(&options)?;
+    let diff = diff::diff(&a_bytes, a, &b_bytes, b);
 
+    match args.format {
+        OutputFormat::Json => {
+            json::write_diff(&diff, io::stdout().lock())?;
+            println!();
+        }
+        _ => print_diff(&diff, &args.a, &args.b),
+    }
     Ok(())
 }
 
-fn main() -> io::Result<()> {
-    configure_rabbitizer();
+/// `options` with every analysis selected, as run by the report subcommand
+fn full_analysis(options: AnalysisOptions) -> AnalysisOptions {
+    AnalysisOptions {
+        find_code_regions: true,
+        determine_compiler: true,
+        find_compressed: true,
+        search_segments: true,
+        find_dma_tables: true,
+        estimate_function_count: true,
+        ngrams: Some(NgramOptions {
+            top: Some(DEFAULT_NGRAM_TOP),
+            ..Default::default()
+        }),
+        rom_map: true,
+        entropy: Some(EntropyOptions::default()),
+        ..options
+    }
+}
+
+fn extract(rom_bytes: &[u8], report: &RomReport, dir: &Path, format: OutputFormat) -> Result<()> {
+    let segments = report.compressed_segments.as_deref().unwrap_or_default();
+    let extracted = extract::extract_all(rom_bytes, segments, dir)?;
 
+    // The json output is only the report, so it can be parsed
+    if format == OutputFormat::Text {
+        println!();
+        println!(
+            "Extracted {} segment{}, listed in {}",
+            extracted.len(),
+            if extracted.len() == 1 { "" } else { "s" },
+            dir.join(extract::MANIFEST_NAME).display()
+        );
+    }
+    Ok(())
+}
+
+fn batch(shared: &Args, args: &BatchArgs) -> Result<()> {
+    let options = AnalysisOptions {
+        determine_compiler: true,
+        find_compressed: true,
+        ipl3_references: load_references(shared.ipl3_dir.as_deref())?,
+        ..Default::default()
+    };
+
+    let rows = batch::analyse_dir(Path::new(&args.dir), &options)?;
+    let stdout = io::stdout().lock();
+    match args.format {
+        BatchFormat::Csv => batch::write_csv(&rows, stdout),
+        BatchFormat::Json => batch::write_json(&rows, stdout),
+    }
+}
+
+fn run(args: Args) -> Result<()> {
+    match &args.command {
+        Command::Info(command) => {
+            let input = args.input(&command.rom);
+            let options = input.analysis_options()?;
+            analyse(&input, &options, command.format, TextOptions::IDENTIFICATION)
+        }
+        Command::Regions(command) => {
+            let input = args.input(&command.rom);
+            let options = AnalysisOptions {
+                find_code_regions: true,
+                estimate_function_count: command.estimate_function_count,
+                search_segments: command.segments,
+                // A splat config marks compressed segments too
+                find_compressed: command.format == OutputFormat::Splat,
+                trace: command.trace.clone(),
+                ..input.analysis_options()?
+            };
+            analyse(&input, &options, command.format, TextOptions::ANALYSIS)
+        }
+        Command::Compiler(command) => {
+            let input = args.input(&command.rom);
+            let options = AnalysisOptions {
+                determine_compiler: true,
+                search_segments: command.segments,
+                ..input.analysis_options()?
+            };
+            analyse(&input, &options, command.format, TextOptions::ANALYSIS)
+        }
+        Command::Compression(command) => {
+            let input = args.input(&command.rom);
+            let options = AnalysisOptions {
+                find_compressed: true,
+                identify_encoders: command.encoders,
+                find_dma_tables: command.tables,
+                ..input.analysis_options()?
+            };
+            let (rom_bytes, report) =
+                analyse_input(&input, &options, command.format, TextOptions::ANALYSIS)?;
+            match &command.extract {
+                Some(dir) => extract(&rom_bytes, &report, Path::new(dir), command.format),
+                None => Ok(()),
+            }
+        }
+        Command::Ngrams(command) => {
+            let input = args.input(&command.rom);
+            let options = AnalysisOptions {
+                ngrams: Some(NgramOptions {
+                    sizes: command.min_n..=command.max_n,
+                    top: command.top.or(command.min_count.is_none().then_some(DEFAULT_NGRAM_TOP)),
+                    min_count: command.min_count,
+                    per_region: command.per_region,
+                }),
+                ..input.analysis_options()?
+            };
+            let text_options = TextOptions {
+                relative_frequencies: command.relative,
+                disassemble_ngrams: command.disasm,
+                ..TextOptions::ANALYSIS
+            };
+            analyse(&input, &options, command.format, text_options)
+        }
+        Command::Map(command) => {
+            let input = args.input(&command.rom);
+            let options = AnalysisOptions {
+                rom_map: true,
+                ..input.analysis_options()?
+            };
+            analyse(&input, &options, command.format, TextOptions::ANALYSIS)
+        }
+        Command::Entropy(command) => {
+            let input = args.input(&command.rom);
+            let defaults = EntropyOptions::default();
+            let options = AnalysisOptions {
+                entropy: Some(EntropyOptions {
+                    window: command.window.unwrap_or(defaults.window),
+                    step: command.step.unwrap_or(defaults.step),
+                    threshold: command.threshold.unwrap_or(defaults.threshold),
+                }),
+                ..input.analysis_options()?
+            };
+            analyse(&input, &options, command.format, TextOptions::ANALYSIS)
+        }
+        Command::Disasm(command) => disasm(&args.input(&command.rom), command),
+        Command::Report(command) => {
+            let input = args.input(&command.rom);
+            let options = full_analysis(AnalysisOptions {
+                trace: command.trace.clone(),
+                ..input.analysis_options()?
+            });
+            analyse(&input, &options, command.format, TextOptions::ALL)
+        }
+        Command::Batch(command) => batch(&args, command),
+        Command::Diff(command) => diff(&args, command),
+        Command::FixCrc(command) => {
+            let (rom_bytes, endian) =
+                n64rom_analyser::load_rom(fs::read(&command.rom)?, args.byte_order, false)?;
+            let references = load_references(args.ipl3_dir.as_deref())?;
+            fix_crc(rom_bytes, endian, &command.output, &references)
+        }
+    }
+}
+
+fn main() {
     // Process arguments
     let args = argh::from_env();
 
-    run(args)
+    if let Err(err) = run(args) {
+        eprintln!("Error: {}", err);
+        std::process::exit(1);
+    }
 }
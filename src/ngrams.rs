@@ -1,14 +1,65 @@
-// use std::collections::HashMap;
-
 use crate::*;
+use crate::utils::*;
 use analysis::*;
 use dashmap::DashMap;
 use findcode::*;
 use rayon::prelude::*;
 use std::hash::BuildHasherDefault;
+use std::ops::RangeInclusive;
 use rustc_hash::FxHasher;
 
 type MyHasher = BuildHasherDefault<FxHasher>;
+/// Frequency and rom address of the first occurrence of each n-gram
+type Summary = DashMap<Vec<rabbitizer::InstrId>, (usize, usize), MyHasher>;
+
+/// Which n-gram tables to produce and how much of each to keep
+#[derive(Debug, Clone)]
+pub struct NgramOptions {
+    /// sizes of n-gram to summarise
+    pub sizes: RangeInclusive<usize>,
+    /// keep only this many of the most frequent entries
+    pub top: Option<usize>,
+    /// keep only entries seen at least this many times
+    pub min_count: Option<usize>,
+    /// produce one table per code region instead of one for the whole rom
+    pub per_region: bool,
+}
+
+impl Default for NgramOptions {
+    fn default() -> Self {
+        Self {
+            sizes: 1..=4,
+            top: None,
+            min_count: None,
+            per_region: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NgramEntry {
+    pub instructions: Vec<rabbitizer::InstrId>,
+    pub count: usize,
+    /// Rom address of the first occurrence, to show an example of the n-gram
+    pub first_rom_addr: usize,
+}
+
+impl NgramEntry {
+    /// Disassemble the first occurrence, one line per instruction
+    pub fn disassemble(&self, rom_bytes: &[u8]) -> Vec<String> {
+        rom_bytes[self.first_rom_addr..]
+            .chunks_exact(INSTRUCTION_SIZE)
+            .take(self.instructions.len())
+            .map(|v| rabbitizer::Instruction::new(read_be_word(v), 0).disassemble(None, 0))
+            .collect()
+    }
+}
+
+/// Instruction name as rabbitizer spells it, without the `cpu_` prefix
+pub fn mnemonic(id: &rabbitizer::InstrId) -> String {
+    let name = format!("{:?}", id);
+    name.strip_prefix("cpu_").unwrap_or(&name).to_string()
+}
 
 fn instr_list(rom_bytes: &[u8], region: &RomRegion) -> Vec<MyInstruction> {
     let region_bytes = &rom_bytes[region.rom_start()..region.rom_end()];
@@ -18,47 +69,120 @@ fn instr_list(rom_bytes: &[u8], region: &RomRegion) -> Vec<MyInstruction> {
         .collect()
 }
 
-fn summary(instrs: &[MyInstruction], n: usize) -> DashMap<Vec<rabbitizer::InstrId>, usize, MyHasher> {
+fn summary(instrs: &[MyInstruction], n: usize, rom_start: usize) -> Summary {
     // Could use Itertools::counts, but for now this avoids yet another dependency
     instrs
         .windows(n)
-        .map(|w| {
-            w.iter()
+        .enumerate()
+        .map(|(i, w)| {
+            let grams = w
+                .iter()
                 .map(|x| x.0.instr_id())
-                .collect::<Vec<rabbitizer::InstrId>>()
+                .collect::<Vec<rabbitizer::InstrId>>();
+            (grams, rom_start + i * INSTRUCTION_SIZE)
         })
-        .into_iter()
-        .fold(DashMap::default(), |map, val| {
-            map.entry(val).and_modify(|frq| *frq += 1).or_insert(1);
+        .fold(DashMap::default(), |map, (val, addr)| {
+            map.entry(val).and_modify(|frq| frq.0 += 1).or_insert((1, addr));
             map
         })
 }
 
-pub fn print_summary(rom_bytes: &[u8], regions: &[RomRegion], n: usize) {
+#[derive(Debug)]
+pub struct NgramTable {
+    pub n: usize,
+    /// The region the table covers, or `None` if it covers every region
+    pub region: Option<RomRegion>,
+    pub region_count: usize,
+    pub instruction_count: usize,
+    /// Number of n-grams counted, i.e. the total of every frequency before any entries were dropped
+    pub gram_count: usize,
+    /// N-grams with their frequencies, most frequent first
+    pub entries: Vec<NgramEntry>,
+}
+
+impl NgramTable {
+    /// Frequency of an entry as a fraction of every n-gram counted
+    pub fn relative_frequency(&self, entry: &NgramEntry) -> f64 {
+        if self.gram_count == 0 {
+            0.0
+        } else {
+            entry.count as f64 / self.gram_count as f64
+        }
+    }
+
+    /// Drop the entries beyond the top `top`, and those seen fewer than `min_count` times
+    pub fn limit(&mut self, top: Option<usize>, min_count: Option<usize>) {
+        if let Some(min_count) = min_count {
+            self.entries.retain(|entry| entry.count >= min_count);
+        }
+        if let Some(top) = top {
+            self.entries.truncate(top);
+        }
+    }
+}
+
+pub fn summarise(rom_bytes: &[u8], regions: &[RomRegion], n: usize) -> NgramTable {
     // No such thing as 0-grams
     assert_ne!(n, 0);
 
-    let out: DashMap<Vec<rabbitizer::InstrId>, usize, MyHasher> = DashMap::default();
+    let out: Summary = DashMap::default();
 
     regions.par_iter().for_each(|r| {
-        for (k, v) in summary(&instr_list(&rom_bytes, r), n) {
-            out.entry(k).and_modify(|val| *val += v).or_insert(v);
+        for (k, (count, addr)) in summary(&instr_list(rom_bytes, r), n, r.rom_start()) {
+            out.entry(k)
+                .and_modify(|val| {
+                    val.0 += count;
+                    val.1 = val.1.min(addr);
+                })
+                .or_insert((count, addr));
         }
     });
 
-    let mut summary_summary = out.into_iter().collect::<Vec<_>>();
-    summary_summary.sort_unstable_by(|x, y| x.1.cmp(&y.1).reverse());
+    let mut entries = out
+        .into_iter()
+        .map(|(instructions, (count, first_rom_addr))| NgramEntry {
+            instructions,
+            count,
+            first_rom_addr,
+        })
+        .collect::<Vec<_>>();
+    // Break ties by address so the order is the same every run
+    entries.sort_unstable_by_key(|entry| (std::cmp::Reverse(entry.count), entry.first_rom_addr));
 
-    
-    let largest = summary_summary.first().unwrap().1;
-    let mut it = summary_summary.iter();
     let instruction_count = regions.iter().fold(0, |a, r| a + (r.rom_end() - r.rom_start() ) / 4 );
+    let gram_count = regions
+        .iter()
+        .map(|r| ((r.rom_end() - r.rom_start()) / INSTRUCTION_SIZE).saturating_sub(n - 1))
+        .sum();
+
+    NgramTable {
+        n,
+        region: None,
+        region_count: regions.len(),
+        instruction_count,
+        gram_count,
+        entries,
+    }
+}
+
+/// Every table selected by `options`, for each size in turn and then each region if they are per-region
+pub fn tables(rom_bytes: &[u8], regions: &[RomRegion], options: &NgramOptions) -> Vec<NgramTable> {
+    let mut tables = Vec::new();
 
-    println!("{n}-grams for {} regions, {} instructions", regions.len(), instruction_count);
-    while let Some(cur) = it.next()  {
-        if cur.1 < largest / 5 {
-            break;
+    for n in options.sizes.clone().filter(|&n| n != 0) {
+        if options.per_region {
+            for region in regions {
+                let mut table = summarise(rom_bytes, std::slice::from_ref(region), n);
+                table.region = Some(region.clone());
+                tables.push(table);
+            }
+        } else {
+            tables.push(summarise(rom_bytes, regions, n));
         }
-        println!("{:?}", it.next().unwrap());
     }
+
+    for table in &mut tables {
+        table.limit(options.top, options.min_count);
+    }
+    tables
 }
@@ -0,0 +1,196 @@
+//! Assigns every 16-byte block of a rom to a category, to show at a glance how much of it is still unexplained.
+
+use std::fmt::Display;
+use std::ops::Range;
+
+use crate::compression::CompressedSegment;
+use crate::findcode::{self, analysis::MyInstruction, RomRegion};
+use crate::header::HEADER_SIZE;
+use crate::utils::*;
+use crate::{INSTRUCTION_SIZE, IPL3_END};
+
+pub const BLOCK_SIZE: usize = 0x10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    Header,
+    Ipl3,
+    CpuCode,
+    RspCode,
+    Compressed,
+    /// Padding of `0x00` or `0xFF`
+    Fill,
+    /// Anything not explained by another category
+    Data,
+}
+
+impl Category {
+    pub const ALL: [Category; 7] = [
+        Category::Header,
+        Category::Ipl3,
+        Category::CpuCode,
+        Category::RspCode,
+        Category::Compressed,
+        Category::Fill,
+        Category::Data,
+    ];
+
+    /// Stable identifier for the category, for machine-readable output
+    pub fn name(&self) -> &'static str {
+        match self {
+            Category::Header => "header",
+            Category::Ipl3 => "ipl3",
+            Category::CpuCode => "cpu_code",
+            Category::RspCode => "rsp_code",
+            Category::Compressed => "compressed",
+            Category::Fill => "fill",
+            Category::Data => "data",
+        }
+    }
+}
+
+impl Display for Category {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Category::Header => "header",
+                Category::Ipl3 => "IPL3",
+                Category::CpuCode => "CPU code",
+                Category::RspCode => "RSP code",
+                Category::Compressed => "compressed",
+                Category::Fill => "fill",
+                Category::Data => "data",
+            }
+        )
+    }
+}
+
+/// Consecutive blocks of the same category
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Run {
+    pub category: Category,
+    pub rom_start: usize,
+    pub rom_end: usize,
+}
+
+impl Run {
+    pub fn size(&self) -> usize {
+        self.rom_end - self.rom_start
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RomMap {
+    pub rom_size: usize,
+    /// Every block of the rom in order, run-length encoded
+    pub runs: Vec<Run>,
+}
+
+/// Whether every byte of a block is the same padding byte
+fn is_fill(block: &[u8]) -> bool {
+    matches!(block.first(), Some(0x00 | 0xFF)) && block.iter().all(|&b| b == block[0])
+}
+
+/// Whether a block in a region with microcode is RSP code, i.e. isn't entirely valid CPU code
+fn is_rsp_block(block: &[u8]) -> bool {
+    block
+        .chunks_exact(INSTRUCTION_SIZE)
+        .any(|word| !findcode::is_valid(&MyInstruction::new(read_be_word(word))))
+}
+
+impl RomMap {
+    /// Classify every block of a big-endian rom. A block that is partly code or compressed data counts as such. A
+    /// compressed segment has been decoded, so it takes precedence over a code region.
+    pub fn build(
+        rom_bytes: &[u8],
+        code_regions: &[RomRegion],
+        compressed_segments: &[CompressedSegment],
+        raw: bool,
+    ) -> RomMap {
+        let block_count = rom_bytes.len().div_ceil(BLOCK_SIZE);
+        let mut blocks: Vec<Option<Category>> = vec![None; block_count];
+
+        if !raw {
+            for (i, block) in blocks.iter_mut().enumerate().take(IPL3_END / BLOCK_SIZE) {
+                *block = Some(if i * BLOCK_SIZE < HEADER_SIZE {
+                    Category::Header
+                } else {
+                    Category::Ipl3
+                });
+            }
+        }
+
+        for region in code_regions {
+            let first = region.rom_start() / BLOCK_SIZE;
+            let last = region.rom_end().div_ceil(BLOCK_SIZE).min(block_count);
+            for i in first..last {
+                let block = &rom_bytes[i * BLOCK_SIZE..((i + 1) * BLOCK_SIZE).min(rom_bytes.len())];
+                blocks[i] = Some(if region.has_rsp() && is_rsp_block(block) {
+                    Category::RspCode
+                } else {
+                    Category::CpuCode
+                });
+            }
+        }
+
+        for seg in compressed_segments {
+            let first = seg.rom_start() / BLOCK_SIZE;
+            let last = seg.rom_end().div_ceil(BLOCK_SIZE).min(block_count);
+            blocks[first..last].fill(Some(Category::Compressed));
+        }
+
+        let mut runs: Vec<Run> = Vec::new();
+        for (i, block) in rom_bytes.chunks(BLOCK_SIZE).enumerate() {
+            let category = blocks[i].unwrap_or(if is_fill(block) {
+                Category::Fill
+            } else {
+                Category::Data
+            });
+            let rom_start = i * BLOCK_SIZE;
+            let rom_end = rom_start + block.len();
+
+            match runs.last_mut() {
+                Some(run) if run.category == category => run.rom_end = rom_end,
+                _ => runs.push(Run {
+                    category,
+                    rom_start,
+                    rom_end,
+                }),
+            }
+        }
+
+        RomMap {
+            rom_size: rom_bytes.len(),
+            runs,
+        }
+    }
+
+    /// Total size of the blocks in a category
+    pub fn bytes(&self, category: Category) -> usize {
+        self.runs
+            .iter()
+            .filter(|run| run.category == category)
+            .map(Run::size)
+            .sum()
+    }
+
+    /// The ranges identified as something other than fill or data
+    pub fn explained(&self) -> Vec<Range<usize>> {
+        self.runs
+            .iter()
+            .filter(|run| !matches!(run.category, Category::Fill | Category::Data))
+            .map(|run| run.rom_start..run.rom_end)
+            .collect()
+    }
+
+    /// Fraction of the rom in a category
+    pub fn coverage(&self, category: Category) -> f64 {
+        if self.rom_size == 0 {
+            0.0
+        } else {
+            self.bytes(category) as f64 / self.rom_size as f64
+        }
+    }
+}
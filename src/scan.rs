@@ -0,0 +1,123 @@
+//! One pass over a rom for every byte pattern something wants to find, e.g. compression magic numbers. Detectors
+//! register their patterns with a `ScannerBuilder`, and each looks up its own hits, sorted, in the `ScanResults`. The
+//! detectors in this crate all register into one `SharedScanner`, which is only built once.
+
+use std::ops::Range;
+use std::sync::OnceLock;
+
+use aho_corasick::AhoCorasick;
+use rayon::prelude::*;
+
+use crate::{compression, ipl3};
+
+/// Size of the pieces the rom is split into to scan in parallel
+const CHUNK_SIZE: usize = 0x100000;
+
+/// Handle to a registered pattern, to look up its hits with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PatternId(usize);
+
+#[derive(Debug, Default)]
+pub struct ScannerBuilder {
+    patterns: Vec<Vec<u8>>,
+    alignments: Vec<usize>,
+}
+
+impl ScannerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a pattern, counted only where it starts on a multiple of `alignment`
+    pub fn register(&mut self, pattern: &[u8], alignment: usize) -> PatternId {
+        self.patterns.push(pattern.to_vec());
+        self.alignments.push(alignment.max(1));
+        PatternId(self.patterns.len() - 1)
+    }
+
+    pub fn build(self) -> Scanner {
+        Scanner {
+            // Only fails if the automaton would be enormous, which a handful of magic numbers can't make it
+            automaton: AhoCorasick::new(&self.patterns).expect("patterns should build an automaton"),
+            max_len: self.patterns.iter().map(Vec::len).max().unwrap_or(0),
+            alignments: self.alignments,
+        }
+    }
+}
+
+/// Every registered pattern, compiled into one automaton
+#[derive(Debug)]
+pub struct Scanner {
+    automaton: AhoCorasick,
+    alignments: Vec<usize>,
+    max_len: usize,
+}
+
+impl Scanner {
+    /// Find every pattern that starts in `range` of `bytes`. A match can run past the end of the range, but not past
+    /// the end of `bytes`. Patterns that overlap are all found.
+    pub fn scan(&self, bytes: &[u8], range: Range<usize>) -> ScanResults {
+        let range = range.start.min(bytes.len())..range.end.min(bytes.len());
+        let found = (range.start..range.end)
+            .into_par_iter()
+            .step_by(CHUNK_SIZE)
+            .flat_map_iter(|chunk_start| {
+                let chunk_end = (chunk_start + CHUNK_SIZE).min(range.end);
+                // Extended so that a match starting near the end of the chunk isn't cut off
+                let haystack = &bytes[chunk_start..(chunk_end + self.max_len).min(bytes.len())];
+                self.automaton
+                    .find_overlapping_iter(haystack)
+                    .map(move |m| (m.pattern().as_usize(), chunk_start + m.start()))
+                    .filter(move |&(pattern, addr)| {
+                        addr < chunk_end && addr.is_multiple_of(self.alignments[pattern])
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let mut hits = vec![Vec::new(); self.alignments.len()];
+        for (pattern, addr) in found {
+            hits[pattern].push(addr);
+        }
+        for pattern_hits in &mut hits {
+            pattern_hits.sort_unstable();
+        }
+        ScanResults { hits }
+    }
+}
+
+/// The scanner for every detector in the crate, with the patterns each one registered
+#[derive(Debug)]
+pub struct SharedScanner {
+    pub scanner: Scanner,
+    pub compression: compression::Signatures,
+    pub ipl3: ipl3::Signatures,
+}
+
+/// The shared scanner, built the first time it is needed
+pub fn shared() -> &'static SharedScanner {
+    static SHARED: OnceLock<SharedScanner> = OnceLock::new();
+    SHARED.get_or_init(|| {
+        let mut builder = ScannerBuilder::new();
+        let compression = compression::Signatures::register(&mut builder);
+        let ipl3 = ipl3::Signatures::register(&mut builder);
+        SharedScanner {
+            scanner: builder.build(),
+            compression,
+            ipl3,
+        }
+    })
+}
+
+/// The addresses each pattern was found at
+#[derive(Debug)]
+pub struct ScanResults {
+    hits: Vec<Vec<usize>>,
+}
+
+impl ScanResults {
+    /// Start addresses of a pattern's matches, in order
+    pub fn hits(&self, pattern: PatternId) -> &[usize] {
+        &self.hits[pattern.0]
+    }
+}
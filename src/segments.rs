@@ -0,0 +1,55 @@
+//! Runs the code analyses over the decompressed contents of compressed segments, where most of the code of a game that
+//! compresses its files lives. Addresses found inside a segment are offsets into its decompressed data.
+
+use rayon::prelude::*;
+
+use crate::compiler::{self, CompilerReport};
+use crate::compression::{CompressedSegment, DecodeError};
+use crate::findcode::{self, RomRegion};
+
+/// What was found in one decompressed segment. The regions' addresses are offsets into the decompressed data.
+#[derive(Debug)]
+pub struct SegmentCode {
+    pub segment: CompressedSegment,
+    pub code_regions: Vec<RomRegion>,
+    pub function_count_estimate: Option<usize>,
+    pub compiler: Option<CompilerReport>,
+}
+
+/// Decompress a segment and look for code in it, in the same way as in the rom itself
+pub fn analyse_segment(
+    rom_bytes: &[u8],
+    segment: &CompressedSegment,
+    estimate_function_count: bool,
+    determine_compiler: bool,
+) -> Result<SegmentCode, DecodeError> {
+    let data = segment.decompress(rom_bytes)?;
+
+    let code_regions = findcode::find_code_regions(&data, 0);
+    let function_count_estimate =
+        estimate_function_count.then(|| findcode::estimate_function_count(&data, 0, &[]));
+    let compiler = determine_compiler.then(|| compiler::analyse(&data, &code_regions));
+
+    Ok(SegmentCode {
+        segment: segment.clone(),
+        code_regions,
+        function_count_estimate,
+        compiler,
+    })
+}
+
+/// Look for code in every segment. Segments are only found if they decode, so none should fail here; any that do are
+/// skipped.
+pub fn analyse_all(
+    rom_bytes: &[u8],
+    segments: &[CompressedSegment],
+    estimate_function_count: bool,
+    determine_compiler: bool,
+) -> Vec<SegmentCode> {
+    segments
+        .par_iter()
+        .filter_map(|segment| {
+            analyse_segment(rom_bytes, segment, estimate_function_count, determine_compiler).ok()
+        })
+        .collect()
+}
@@ -0,0 +1,218 @@
+//! Writes a skeleton [splat](https://github.com/ethteck/splat) config from a report. It is only a starting point:
+//! segment boundaries come straight from the heuristics, and code outside the boot segment gets a placeholder VRAM.
+
+use std::collections::HashSet;
+use std::io::{self, Write};
+
+use crate::compiler::Compiler;
+use crate::compression;
+use crate::header::HEADER_SIZE;
+use crate::utils::*;
+use crate::{RomReport, BOOT_SEGMENT_SIZE, IPL3_END};
+
+/// How far to look for the `jr` at the end of the entry function
+const ENTRY_SEARCH_SIZE: usize = 0x100;
+/// Size of the usual libultra entry function, if the `jr` is not found
+const DEFAULT_ENTRY_SIZE: usize = 0x60;
+const PLACEHOLDER_VRAM: u32 = 0x80000000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Piece {
+    Code { has_rsp: bool },
+    Compressed(compression::Type),
+    Bin,
+}
+
+impl Piece {
+    fn splat_type(&self) -> &'static str {
+        match self {
+            Piece::Code { has_rsp: false } => "asm",
+            Piece::Code { has_rsp: true } => "rsp",
+            // Formats splat can't decompress are left as they are
+            Piece::Compressed(algorithm) => algorithm.splat_type().unwrap_or("bin"),
+            Piece::Bin => "bin",
+        }
+    }
+}
+
+/// The compiler option splat expects, if the heuristics narrowed it down to one family
+fn splat_compiler(possible_compilers: &HashSet<Compiler>) -> Option<&'static str> {
+    let families = possible_compilers
+        .iter()
+        .map(|compiler| match compiler {
+            Compiler::IDO53 | Compiler::IDO71 => "IDO",
+            Compiler::KMCGCC => "KMC",
+            Compiler::ISGCC => "GCC",
+            Compiler::SN64 | Compiler::SNCXX => "SN64",
+            Compiler::MWCC => "MWCC",
+        })
+        .collect::<HashSet<_>>();
+
+    if families.len() == 1 {
+        families.into_iter().next()
+    } else {
+        None
+    }
+}
+
+/// End of the entry function: the first `jr` and its delay slot, rounded up to 0x10
+fn find_entry_end(rom_bytes: &[u8]) -> usize {
+    let search_end = (IPL3_END + ENTRY_SEARCH_SIZE).min(round_down(rom_bytes.len(), 4));
+    (IPL3_END..search_end)
+        .step_by(4)
+        .find(|&addr| read_be_word(&rom_bytes[addr..]) & 0xFC1FFFFF == 0x00000008)
+        .map_or(IPL3_END + DEFAULT_ENTRY_SIZE, |jr| round_up(jr + 8, 0x10))
+}
+
+/// Every code region and compressed segment from `start` onwards, sorted, with the gaps between them filled with `bin`
+fn pieces(report: &RomReport, start: usize, rom_len: usize) -> Vec<(usize, Piece)> {
+    let mut found = report
+        .code_regions
+        .iter()
+        .flatten()
+        .map(|region| {
+            let piece = Piece::Code {
+                has_rsp: region.has_rsp(),
+            };
+            (region.rom_start().max(start), region.rom_end(), piece)
+        })
+        .filter(|(_, end, _)| *end > start)
+        .collect::<Vec<_>>();
+    if let Some(segments) = &report.compressed_segments {
+        found.extend(
+            segments
+                .iter()
+                .filter(|seg| seg.rom_start() >= start)
+                .map(|seg| (seg.rom_start(), seg.rom_end(), Piece::Compressed(seg.algorithm()))),
+        );
+    }
+    found.sort_by_key(|(start, _, _)| *start);
+
+    let mut pieces = Vec::new();
+    let mut pos = start;
+    for (piece_start, piece_end, piece) in found {
+        if piece_start < pos {
+            continue;
+        }
+        if piece_start > pos {
+            pieces.push((pos, Piece::Bin));
+        }
+        pieces.push((piece_start, piece));
+        pos = piece_end.max(piece_start);
+    }
+    if pos < rom_len {
+        pieces.push((pos, Piece::Bin));
+    }
+
+    pieces
+}
+
+/// Write a splat config for a big-endian rom. `rom_path` is used as the target path and to name the output.
+pub fn write_config(
+    report: &RomReport,
+    rom_bytes: &[u8],
+    rom_path: &str,
+    mut writer: impl Write,
+) -> io::Result<()> {
+    let basename = std::path::Path::new(rom_path)
+        .file_stem()
+        .map_or_else(|| "rom".to_string(), |stem| stem.to_string_lossy().to_lowercase());
+    let name = report
+        .header
+        .as_ref()
+        .map_or(basename.clone(), |header| header.name.clone());
+
+    writeln!(writer, "name: {:?}", name)?;
+    writeln!(writer, "options:")?;
+    writeln!(writer, "  basename: {}", basename)?;
+    writeln!(writer, "  target_path: {}", rom_path)?;
+    writeln!(writer, "  base_path: .")?;
+    writeln!(writer, "  platform: n64")?;
+    match report
+        .compiler
+        .as_ref()
+        .and_then(|compiler| splat_compiler(&compiler.possible_compilers))
+    {
+        Some(compiler) => writeln!(writer, "  compiler: {}", compiler)?,
+        None => writeln!(writer, "  # compiler: could not be determined")?,
+    }
+    writeln!(writer, "  asm_path: asm")?;
+    writeln!(writer, "  src_path: src")?;
+    writeln!(writer, "  build_path: build")?;
+    writeln!(writer)?;
+
+    writeln!(writer, "segments:")?;
+    writeln!(writer, "  - name: header")?;
+    writeln!(writer, "    type: header")?;
+    writeln!(writer, "    start: 0x0")?;
+    writeln!(writer)?;
+    writeln!(writer, "  - name: ipl3")?;
+    writeln!(writer, "    type: bin")?;
+    writeln!(writer, "    start: 0x{:X}", HEADER_SIZE)?;
+    writeln!(writer)?;
+
+    let entrypoint = report.entrypoint.unwrap_or_else(|| {
+        report
+            .header
+            .as_ref()
+            .map_or(PLACEHOLDER_VRAM, |header| header.entrypoint)
+    });
+    let entry_end = find_entry_end(rom_bytes);
+    writeln!(writer, "  - name: entry")?;
+    writeln!(writer, "    type: code")?;
+    writeln!(writer, "    start: 0x{:X}", IPL3_END)?;
+    writeln!(writer, "    vram: 0x{:08X}", entrypoint)?;
+    writeln!(writer, "    subsegments:")?;
+    writeln!(writer, "      - [0x{:X}, hasm]", IPL3_END)?;
+    writeln!(writer)?;
+
+    // Everything up to the end of the last code region that starts in the boot segment is assumed to be `main`
+    let pieces = pieces(report, entry_end, rom_bytes.len());
+    let boot_end = IPL3_END + BOOT_SEGMENT_SIZE;
+    let main_end = report
+        .code_regions
+        .iter()
+        .flatten()
+        .filter(|region| region.rom_start() < boot_end)
+        .map(|region| region.rom_end())
+        .max()
+        .unwrap_or(entry_end)
+        .max(entry_end);
+
+    if main_end > entry_end {
+        writeln!(writer, "  - name: main")?;
+        writeln!(writer, "    type: code")?;
+        writeln!(writer, "    start: 0x{:X}", entry_end)?;
+        writeln!(
+            writer,
+            "    vram: 0x{:08X}",
+            entrypoint.wrapping_add((entry_end - IPL3_END) as u32)
+        )?;
+        writeln!(writer, "    follows_vram: entry")?;
+        writeln!(writer, "    subsegments:")?;
+        for (start, piece) in pieces.iter().filter(|(start, _)| *start < main_end) {
+            writeln!(writer, "      - [0x{:X}, {}]", start, piece.splat_type())?;
+        }
+        writeln!(writer)?;
+    }
+
+    for (start, piece) in pieces.iter().filter(|(start, _)| *start >= main_end) {
+        match piece {
+            Piece::Code { .. } => {
+                writeln!(writer)?;
+                writeln!(writer, "  - name: code_{:X}", start)?;
+                writeln!(writer, "    type: code")?;
+                writeln!(writer, "    start: 0x{:X}", start)?;
+                writeln!(writer, "    vram: 0x{:08X} # unknown", PLACEHOLDER_VRAM)?;
+                writeln!(writer, "    subsegments:")?;
+                writeln!(writer, "      - [0x{:X}, {}]", start, piece.splat_type())?;
+                writeln!(writer)?;
+            }
+            _ => writeln!(writer, "  - [0x{:X}, {}]", start, piece.splat_type())?,
+        }
+    }
+
+    writeln!(writer, "  - [0x{:X}]", rom_bytes.len())?;
+
+    Ok(())
+}
@@ -1,5 +1,6 @@
+use crate::error::{AnalyserError, Result};
 use crate::{INSTRUCTION_SIZE, WORD_SIZE};
-use std::io;
+use std::str::FromStr;
 
 #[derive(Debug, Clone, Copy)]
 pub enum Endian {
@@ -8,19 +9,43 @@ pub enum Endian {
     Ugly,
 }
 
-pub fn get_endian(input: &[u8]) -> io::Result<Endian> {
-    match &input[0..WORD_SIZE] {
+impl FromStr for Endian {
+    type Err = String;
+
+    /// Accepts the usual file extension for each byte order
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "z64" | "big" => Ok(Endian::Good),
+            "n64" | "little" => Ok(Endian::Bad),
+            "v64" | "byteswapped" => Ok(Endian::Ugly),
+            _ => Err(format!("unknown byte order `{}`, expected z64, n64 or v64", s)),
+        }
+    }
+}
+
+pub fn get_endian(input: &[u8]) -> Result<Endian> {
+    let magic: [u8; WORD_SIZE] = input
+        .get(0..WORD_SIZE)
+        .and_then(|x| x.try_into().ok())
+        .ok_or(AnalyserError::TruncatedRom {
+            size: input.len(),
+            expected: WORD_SIZE,
+        })?;
+
+    match magic {
         [0x80, 0x37, 0x12, 0x40] => Ok(Endian::Good),
         [0x40, 0x12, 0x37, 0x80] => Ok(Endian::Bad),
         [0x37, 0x80, 0x40, 0x12] => Ok(Endian::Ugly),
-        _ => panic!("Unrecognised header format"),
+        _ => Err(AnalyserError::UnknownByteOrder(magic)),
     }
 }
 
 /// Re-ends an array in-place
-pub fn reend_array(v: &mut [u8], endian: &Endian) {
+pub fn reend_array(v: &mut [u8], endian: &Endian) -> Result<()> {
     let length = v.len();
-    assert!(length % INSTRUCTION_SIZE == 0);
+    if length % INSTRUCTION_SIZE != 0 {
+        return Err(AnalyserError::BadSize(length));
+    }
     match endian {
         Endian::Good => (),
         Endian::Bad => {
@@ -34,6 +59,7 @@ pub fn reend_array(v: &mut [u8], endian: &Endian) {
             }
         }
     };
+    Ok(())
 }
 
 use ::num_traits;